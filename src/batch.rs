@@ -0,0 +1,212 @@
+use crate::{
+    calculate_mu, get_calc_param_input, load_data, load_manifest, load_tolerance_table,
+    manifest_hash, mu_difference, CalcParam, CorrectionData, EmuError, ToleranceTable, Verdict,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Result of the MU check of one row in a batch.
+/// The calculated values are empty if the row could not be checked.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchResult {
+    pub row: usize,
+    pub machine: String,
+    pub applicator: String,
+    pub energy: Option<f64>,
+    pub fda_id: Option<usize>,
+    pub ssd: Option<f64>,
+    pub dose_zref: Option<f64>,
+    pub planned_beam_mu: Option<f64>,
+    pub mu: Option<f64>,
+    pub difference: Option<f64>,
+    pub verdict: Option<Verdict>,
+    pub error: String,
+    pub manifest_version: String, // empty if the configuration data has no manifest
+    pub manifest_hash: String,    // SHA-256 hash of the manifest
+}
+
+impl BatchResult {
+    fn new(row: usize) -> Self {
+        Self {
+            row,
+            machine: "".to_owned(),
+            applicator: "".to_owned(),
+            energy: None,
+            fda_id: None,
+            ssd: None,
+            dose_zref: None,
+            planned_beam_mu: None,
+            mu: None,
+            difference: None,
+            verdict: None,
+            error: "".to_owned(),
+            manifest_version: "".to_owned(),
+            manifest_hash: "".to_owned(),
+        }
+    }
+
+    fn set_calc_param(&mut self, calc_param: &CalcParam) {
+        self.machine = calc_param.machine.clone();
+        self.applicator = calc_param.applicator.clone();
+        if calc_param.has_energy() {
+            self.energy = Some(calc_param.energy);
+        }
+        if calc_param.has_fda_id() {
+            self.fda_id = Some(calc_param.fda_id);
+        }
+        if calc_param.has_ssd() {
+            self.ssd = Some(calc_param.ssd);
+        }
+        if calc_param.has_dose_zref() {
+            self.dose_zref = Some(calc_param.dose_zref);
+        }
+        if calc_param.has_planned_beam_mu() {
+            self.planned_beam_mu = Some(calc_param.planned_beam_mu);
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.error.is_empty()
+    }
+}
+
+// Parse the calculation parameters from CSV data with a header row.
+// Each row is parsed separately so an invalid row doesn't prevent the others from being read.
+fn parse_batch_input<R: std::io::Read>(rdr: R) -> Vec<Result<CalcParam, EmuError>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(rdr);
+    rdr.deserialize::<CalcParam>()
+        .map(|res| res.map_err(|e| EmuError::Format(e.to_string())))
+        .collect()
+}
+
+/// Read the calculation parameters of a batch from a CSV file.
+/// The header names the CalcParam fields, columns may be omitted if
+/// the field isn't required (e.g. depth_zref).
+pub fn read_batch_input(path_buf: PathBuf) -> Result<Vec<Result<CalcParam, EmuError>>, EmuError> {
+    let file = std::fs::File::open(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    Ok(parse_batch_input(file))
+}
+
+/// Check the MUs of each row in the batch and judge the difference using the tolerance rules.
+pub fn run_batch(
+    vcd: &[CorrectionData],
+    inputs: &[Result<CalcParam, EmuError>],
+    tolerances: &ToleranceTable,
+) -> Vec<BatchResult> {
+    let mut v = Vec::with_capacity(inputs.len());
+    for (i, input) in inputs.iter().enumerate() {
+        let mut res = BatchResult::new(i + 1);
+        let res_mu = input.clone().and_then(|input_params| {
+            res.set_calc_param(&input_params);
+            let (calc_param, cd) = get_calc_param_input(vcd, Some(&input_params), false)?;
+            Ok((calculate_mu(&calc_param, cd)?, calc_param))
+        });
+        match res_mu {
+            Ok((mu, calc_param)) => {
+                let diff = mu_difference(calc_param.planned_beam_mu, mu);
+                res.mu = Some(mu);
+                res.difference = Some(diff);
+                res.verdict = Some(tolerances.verdict(
+                    &calc_param.machine,
+                    calc_param.energy,
+                    &calc_param.applicator,
+                    diff,
+                ));
+            }
+            Err(e) => res.error = e.to_string(),
+        }
+        v.push(res);
+    }
+    v
+}
+
+/// Write the results of a batch to a CSV file.
+pub fn write_batch_results(path_buf: PathBuf, results: &[BatchResult]) -> Result<(), EmuError> {
+    let mut wtr = csv::Writer::from_path(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    for res in results {
+        wtr.serialize(res)
+            .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Load the configuration data and tolerance rules, check the MUs of each row in the input
+/// CSV file and write the results to the output CSV file.
+pub async fn load_data_batch(
+    dirname: &str,
+    input: &str,
+    output: &str,
+) -> Result<Vec<BatchResult>, EmuError> {
+    let vcd = load_data(dirname).await?;
+    let tolerances = load_tolerance_table(dirname)?;
+    let inputs = read_batch_input(PathBuf::from(input))?;
+    let mut results = run_batch(&vcd, &inputs, &tolerances);
+    // Identify the approved version of the data on every row.
+    if let (Some(manifest), Some(hash)) = (load_manifest(dirname)?, manifest_hash(dirname)?) {
+        for res in &mut results {
+            res.manifest_version = manifest.version.clone();
+            res.manifest_hash = hash.clone();
+        }
+    }
+    write_batch_results(PathBuf::from(output), &results)?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn batch_rows() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let data = "\
+machine,applicator,energy,fda_id,ssd,dose_zref,planned_beam_mu
+Synergy2,10x10,12,9,99.2,100,118.046388925549
+Synergy2,10x10,6,9,95,100,110
+Synergy2,10x10,7,9,95,100,110
+Synergy2,10x10,abc,9,95,100,110
+Synergy2,6x6,6,10,95,100,104.931794333683
+";
+        let inputs = parse_batch_input(data.as_bytes());
+        assert_eq!(inputs.len(), 5);
+        let results = run_batch(&vcd, &inputs, &ToleranceTable::default());
+        assert_eq!(results.len(), 5);
+
+        assert!(results[0].is_ok());
+        assert_eq!(results[0].verdict, Some(Verdict::Pass));
+        assert!(results[0].difference.unwrap().abs() < 1e-6);
+
+        // planned MUs deviate more than 5%
+        assert!(results[1].is_ok());
+        assert_eq!(results[1].verdict, Some(Verdict::Fail));
+
+        // energy not in the table
+        assert!(!results[2].is_ok());
+        assert_eq!(results[2].verdict, None);
+        assert_eq!(results[2].machine, "Synergy2");
+
+        // invalid energy
+        assert!(!results[3].is_ok());
+        assert_eq!(results[3].row, 4);
+
+        assert_eq!(results[4].verdict, Some(Verdict::Pass));
+
+        let dir =
+            std::env::temp_dir().join(format!("emu_check_batch_results_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tmp = dir.join("results.csv");
+        write_batch_results(tmp.clone(), &results).unwrap();
+        let content = std::fs::read_to_string(&tmp).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(content
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with(",error,manifest_version,manifest_hash"));
+    }
+}
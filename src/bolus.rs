@@ -0,0 +1,106 @@
+use crate::{CalcParam, EmuError};
+use serde::{Deserialize, Serialize};
+
+/// Change of the SSD and the depth of zref caused by bolus on the skin.
+/// The SSD of the calculation parameters is the SSD to the skin, the output factor is looked
+/// up at the SSD to the bolus surface. zref lies at its depth below the bolus surface, which
+/// is the depth below the skin minus the water equivalent thickness of the bolus.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BolusAdjustment {
+    pub thickness: f64,         // geometric thickness [cm]
+    pub water_equivalence: f64, // water equivalent thickness per cm of bolus
+    pub ssd: f64,               // SSD [cm] to the bolus surface
+    pub depth_zref: f64,        // depth [cm] of zref below the skin
+}
+
+impl BolusAdjustment {
+    /// Water equivalent thickness [cm] of the bolus.
+    pub fn water_equivalent_thickness(&self) -> f64 {
+        self.thickness * self.water_equivalence
+    }
+}
+
+impl CalcParam {
+    pub fn has_bolus(&self) -> bool {
+        self.bolus_thickness.is_some()
+    }
+
+    /// Adjustment of the SSD and the depth of zref for the bolus, None without bolus.
+    /// The water equivalence defaults to 1.0. The bolus must not be thicker than the depth
+    /// of zref, zref can't lie inside the bolus.
+    pub fn bolus_adjustment(&self) -> Result<Option<BolusAdjustment>, EmuError> {
+        let thickness = match self.bolus_thickness {
+            Some(thickness) => thickness,
+            None => return Ok(None),
+        };
+        if !(thickness.is_finite() && thickness >= 0.0) {
+            return Err(EmuError::InvalidInput(
+                "bolus_thickness".to_owned(),
+                thickness.to_string(),
+            ));
+        }
+        let water_equivalence = self.bolus_water_equivalence.unwrap_or(1.0);
+        if !(water_equivalence.is_finite() && water_equivalence > 0.0) {
+            return Err(EmuError::InvalidInput(
+                "bolus_water_equivalence".to_owned(),
+                water_equivalence.to_string(),
+            ));
+        }
+        if thickness >= self.ssd {
+            return Err(EmuError::InvalidInput(
+                "bolus_thickness".to_owned(),
+                format!("{} (SSD is {} cm)", thickness, self.ssd),
+            ));
+        }
+        let depth_zref = self.depth_zref - thickness * water_equivalence;
+        if depth_zref < 0.0 {
+            return Err(EmuError::InvalidInput(
+                "bolus_thickness".to_owned(),
+                format!(
+                    "{} (zref at {} cm lies inside the bolus)",
+                    thickness, self.depth_zref
+                ),
+            ));
+        }
+        Ok(Some(BolusAdjustment {
+            thickness,
+            water_equivalence,
+            ssd: self.ssd - thickness,
+            depth_zref,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bolus_adjustment() {
+        let mut cp = CalcParam::new();
+        cp.ssd = 100.0;
+        cp.depth_zref = 2.78;
+        assert!(cp.bolus_adjustment().unwrap().is_none());
+
+        cp.bolus_thickness = Some(1.0);
+        cp.bolus_water_equivalence = Some(0.9);
+        let bolus = cp.bolus_adjustment().unwrap().unwrap();
+        assert_eq!(bolus.ssd, 99.0);
+        assert!((bolus.depth_zref - 1.88).abs() < 1e-12);
+        assert!((bolus.water_equivalent_thickness() - 0.9).abs() < 1e-12);
+
+        cp.bolus_water_equivalence = Some(0.0);
+        assert!(cp.bolus_adjustment().is_err());
+        cp.bolus_water_equivalence = None;
+        cp.bolus_thickness = Some(-0.5);
+        assert!(cp.bolus_adjustment().is_err());
+        // zref at 2.78 cm below the skin lies inside 3 cm of bolus
+        cp.bolus_thickness = Some(3.0);
+        assert!(matches!(
+            cp.bolus_adjustment(),
+            Err(EmuError::InvalidInput(_, _))
+        ));
+        cp.bolus_thickness = Some(2.78);
+        assert_eq!(cp.bolus_adjustment().unwrap().unwrap().depth_zref, 0.0);
+    }
+}
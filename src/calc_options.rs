@@ -0,0 +1,85 @@
+use crate::{EmuError, InterpolationMethod};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the file in the directory with the configuration data that sets the calculation options.
+pub const CALC_OPTIONS_FILE_NAME: &str = "calc_options.json";
+
+/// Options of the MU calculation that apply to the configuration data.
+/// All options are disabled by default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalcOptions {
+    /// Interpolate linearly between the neighbouring tabulated energies
+    /// if the energy of the beam isn't tabulated.
+    pub energy_interpolation: bool,
+    /// Model of the output factor as a function of the SSD.
+    pub ssd_model: SsdModel,
+    /// Distance [cm] beyond the tabulated SSDs up to which the virtual source model
+    /// may be extrapolated.
+    pub ssd_extrapolation: f64,
+    /// Interpolation method of the tabulated output factors between SSDs.
+    pub ssd_interpolation: InterpolationMethod,
+    /// Also compute the MUs with every interpolation method to show how much
+    /// the choice of the method affects the result.
+    pub compare_interpolation: bool,
+    /// Relative standard uncertainty [%] of the dose at zref, combined with the
+    /// uncertainties of the tabulated factors.
+    pub dose_uncertainty: f64,
+    /// Options that override the options of the dataset for a machine.
+    pub machines: BTreeMap<String, MachineCalcOptions>,
+}
+
+/// Calculation options of a machine, options that aren't set use the options of the dataset.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MachineCalcOptions {
+    pub ssd_interpolation: Option<InterpolationMethod>,
+}
+
+impl CalcOptions {
+    /// Interpolation method between SSDs for a machine.
+    pub fn ssd_interpolation_for(&self, machine: &str) -> InterpolationMethod {
+        self.machines
+            .get(machine)
+            .and_then(|m| m.ssd_interpolation)
+            .unwrap_or(self.ssd_interpolation)
+    }
+}
+
+/// Model of the output factor as a function of the SSD.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SsdModel {
+    /// Interpolate linearly between the tabulated SSDs.
+    #[default]
+    Tabulated,
+    /// Inverse square law from a virtual source, fitted per energy and applicator
+    /// to the tabulated output factors.
+    VirtualSource,
+}
+
+/// Read the calculation options from a JSON file.
+pub fn read_calc_options(path_buf: PathBuf) -> Result<CalcOptions, EmuError> {
+    let content = std::fs::read_to_string(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    serde_json::from_str(&content).map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))
+}
+
+pub fn write_calc_options(path_buf: PathBuf, options: &CalcOptions) -> Result<(), EmuError> {
+    let content = serde_json::to_string_pretty(options)
+        .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+    std::fs::write(&path_buf, content).map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))
+}
+
+/// Load the calculation options stored next to the configuration data.
+/// If the directory has no calculation options file, the default options are used.
+pub fn load_calc_options(dirname: &str) -> Result<CalcOptions, EmuError> {
+    let path_buf = Path::new(dirname).join(CALC_OPTIONS_FILE_NAME);
+    if path_buf.is_file() {
+        read_calc_options(path_buf)
+    } else {
+        Ok(CalcOptions::default())
+    }
+}
@@ -1,6 +1,9 @@
+use crate::Cutout;
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct CalcParam {
     pub machine: String,
     pub applicator: String,
@@ -10,6 +13,14 @@ pub struct CalcParam {
     pub dose_zref: f64,
     pub planned_beam_mu: f64,
     pub fda_id: usize,
+    pub cutout: Option<Cutout>,               // replaces the FDA if set
+    pub prescription_dose: Option<f64>,       // dose [cGy] at prescription_depth, sets dose_zref
+    pub prescription_depth: Option<f64>,      // depth [cm] of the prescribed dose
+    pub calc_date: Option<NaiveDate>,         // selects the calibration, today if None
+    pub incidence_angle: Option<f64>,         // angle [degrees] of the beam axis to the skin normal
+    pub bolus_thickness: Option<f64>,         // thickness [cm] of the bolus on the skin
+    pub bolus_water_equivalence: Option<f64>, // water equivalent cm per cm, 1.0 if None
+    pub shield_depth: Option<f64>,            // depth [cm] of the internal shield interface
 }
 
 impl CalcParam {
@@ -22,7 +33,15 @@ impl CalcParam {
             depth_zref: 0.0,
             dose_zref: 0.0,
             planned_beam_mu: 0.0,
-            fda_id: std::usize::MAX,
+            fda_id: usize::MAX,
+            cutout: None,
+            prescription_dose: None,
+            prescription_depth: None,
+            calc_date: None,
+            incidence_angle: None,
+            bolus_thickness: None,
+            bolus_water_equivalence: None,
+            shield_depth: None,
         }
     }
 
@@ -55,7 +74,15 @@ impl CalcParam {
     }
 
     pub fn has_fda_id(&self) -> bool {
-        self.fda_id != std::usize::MAX
+        self.fda_id != usize::MAX
+    }
+
+    pub fn has_cutout(&self) -> bool {
+        self.cutout.is_some()
+    }
+
+    pub fn has_prescription(&self) -> bool {
+        self.prescription_dose.is_some() && self.prescription_depth.is_some()
     }
 }
 
@@ -67,8 +94,33 @@ impl Default for CalcParam {
 
 impl std::fmt::Display for CalcParam {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Machine: {}\nApplicator: {}\nEnergy(MeV): {}\nSSD[cm]: {}\nZref(depth[cm]): {}\nZref(dose[cGy]): {}\nFDA ID: {}\nMU(plan): {}\n",
-        self.machine, self.applicator, self.energy, self.ssd, self.depth_zref, self.dose_zref, self.fda_id, self.planned_beam_mu
-        )
+        write!(f, "Machine: {}\nApplicator: {}\nEnergy(MeV): {}\nSSD[cm]: {}\nZref(depth[cm]): {}\nZref(dose[cGy]): {}\n",
+        self.machine, self.applicator, self.energy, self.ssd, self.depth_zref, self.dose_zref
+        )?;
+        match &self.cutout {
+            Some(cutout) => writeln!(f, "Cutout: {}", cutout)?,
+            None => writeln!(f, "FDA ID: {}", self.fda_id)?,
+        }
+        if let (Some(dose), Some(depth)) = (self.prescription_dose, self.prescription_depth) {
+            writeln!(f, "Prescription: {} cGy at depth {} cm", dose, depth)?;
+        }
+        if let Some(thickness) = self.bolus_thickness {
+            writeln!(
+                f,
+                "Bolus[cm]: {} (water equivalence: {})",
+                thickness,
+                self.bolus_water_equivalence.unwrap_or(1.0)
+            )?;
+        }
+        if let Some(depth) = self.shield_depth {
+            writeln!(f, "Internal shield (depth[cm]): {}", depth)?;
+        }
+        if let Some(angle) = self.incidence_angle {
+            writeln!(f, "Incidence angle[deg]: {}", angle)?;
+        }
+        if let Some(date) = self.calc_date {
+            writeln!(f, "Calculation date: {}", date)?;
+        }
+        writeln!(f, "MU(plan): {}", self.planned_beam_mu)
     }
 }
@@ -0,0 +1,269 @@
+use crate::ipol::{find_neighbours, interpolate_linear};
+use crate::{CorrectionData, EmuError};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Name of the calibration file in the directory with the configuration data.
+pub const CALIBRATION_FILE_NAME: &str = "calibration.csv";
+
+/// Reference dose per MU of a machine and energy, effective from a date onwards.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub machine: String,
+    pub energy: f64,
+    pub cgy_per_mu: f64, // dose [cGy] per MU at zref under reference conditions
+    pub effective_date: NaiveDate,
+}
+
+impl std::fmt::Display for Calibration {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} cGy/MU (effective from {})",
+            self.cgy_per_mu, self.effective_date
+        )
+    }
+}
+
+/// Calibration history of the machines.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CalibrationTable {
+    pub calibrations: Vec<Calibration>,
+}
+
+impl CalibrationTable {
+    pub fn new() -> Self {
+        Self {
+            calibrations: vec![],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calibrations.is_empty()
+    }
+
+    /// Calibrations of a machine.
+    pub fn machine(&self, machine: &str) -> Vec<Calibration> {
+        self.calibrations
+            .iter()
+            .filter(|c| c.machine == machine)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Get the calibration valid on the date: the calibration of the machine and energy
+/// with the latest effective date that is not after the date.
+pub fn find_calibration<'a>(
+    calibrations: &'a [Calibration],
+    machine: &str,
+    energy: f64,
+    date: NaiveDate,
+) -> Option<&'a Calibration> {
+    calibrations
+        .iter()
+        .filter(|c| {
+            c.machine == machine
+                && (c.energy - energy).abs() < f64::EPSILON
+                && c.effective_date <= date
+        })
+        .max_by_key(|c| c.effective_date)
+}
+
+/// Get the calibration valid on the date, interpolated linearly between the calibrations
+/// of the nearest lower and higher energy of the machine if the energy isn't calibrated.
+/// The interpolated calibration is effective from the later of the two dates.
+pub fn interpolate_calibration(
+    calibrations: &[Calibration],
+    machine: &str,
+    energy: f64,
+    date: NaiveDate,
+) -> Option<Calibration> {
+    if let Some(c) = find_calibration(calibrations, machine, energy, date) {
+        return Some(c.clone());
+    }
+    let mut energies: Vec<f64> = calibrations
+        .iter()
+        .filter(|c| c.machine == machine && c.effective_date <= date)
+        .map(|c| c.energy)
+        .collect();
+    energies.sort_by(|a, b| a.total_cmp(b));
+    energies.dedup();
+    let (i0, i1) = find_neighbours(&energies, energy)?;
+    let c0 = find_calibration(calibrations, machine, energies[i0], date)?;
+    let c1 = find_calibration(calibrations, machine, energies[i1], date)?;
+    Some(Calibration {
+        machine: machine.to_owned(),
+        energy,
+        cgy_per_mu: interpolate_linear(energy, c0.energy, c1.energy, c0.cgy_per_mu, c1.cgy_per_mu),
+        effective_date: c0.effective_date.max(c1.effective_date),
+    })
+}
+
+/// Current local date.
+pub fn today() -> NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+fn parse_calibration_table<R: std::io::Read>(rdr: R) -> Result<CalibrationTable, EmuError> {
+    let mut table = CalibrationTable::new();
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(rdr);
+    for record in rdr.deserialize::<Calibration>() {
+        let calibration = record.map_err(|e| EmuError::Format(e.to_string()))?;
+        if !(calibration.cgy_per_mu > 0.0 && calibration.cgy_per_mu.is_finite()) {
+            return Err(EmuError::Format(format!(
+                "The calibration of machine [{}] and energy [{}] must be a positive number [{}]",
+                calibration.machine, calibration.energy, calibration.cgy_per_mu
+            )));
+        }
+        if let Some(other) = table.calibrations.iter().find(|c| {
+            c.machine == calibration.machine
+                && (c.energy - calibration.energy).abs() < f64::EPSILON
+                && c.effective_date == calibration.effective_date
+        }) {
+            return Err(EmuError::Format(format!(
+                "Duplicate calibration of machine [{}] and energy [{}] effective from {}",
+                other.machine, other.energy, other.effective_date
+            )));
+        }
+        table.calibrations.push(calibration);
+    }
+    Ok(table)
+}
+
+/// Read the calibrations from a CSV file with the header
+/// `machine,energy,cgy_per_mu,effective_date` (dates as YYYY-MM-DD).
+pub fn read_calibration_table(path_buf: PathBuf) -> Result<CalibrationTable, EmuError> {
+    let file = std::fs::File::open(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    parse_calibration_table(file).map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))
+}
+
+pub fn write_calibration_table(
+    path_buf: PathBuf,
+    table: &CalibrationTable,
+) -> Result<(), EmuError> {
+    let mut wtr = csv::Writer::from_path(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    for calibration in &table.calibrations {
+        wtr.serialize(calibration)
+            .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    }
+    wtr.flush()
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))
+}
+
+/// Load the calibrations stored next to the configuration data.
+/// If the directory has no calibration file, the table is empty.
+pub fn load_calibration_table(dirname: &str) -> Result<CalibrationTable, EmuError> {
+    let path_buf = Path::new(dirname).join(CALIBRATION_FILE_NAME);
+    if path_buf.is_file() {
+        read_calibration_table(path_buf)
+    } else {
+        Ok(CalibrationTable::default())
+    }
+}
+
+impl CorrectionData {
+    /// Get the calibration of the energy valid on the date.
+    /// None if the machine has no calibrations, the tables are then assumed to be
+    /// normalised to 1 cGy/MU.
+    /// With energy interpolation, an energy without calibration gets the calibration
+    /// interpolated between the calibrated energies (see interpolate_calibration).
+    pub fn get_calibration(
+        &self,
+        energy: f64,
+        date: NaiveDate,
+    ) -> Result<Option<Calibration>, EmuError> {
+        if self.calibrations.is_empty() {
+            return Ok(None);
+        }
+        let calibration = if self.options.energy_interpolation {
+            interpolate_calibration(&self.calibrations, &self.machine, energy, date)
+        } else {
+            find_calibration(&self.calibrations, &self.machine, energy, date).cloned()
+        };
+        calibration
+            .map(Some)
+            .ok_or_else(|| EmuError::CalibrationNotFound(self.machine.clone(), energy, date))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn calibration_history() {
+        let data = "\
+machine,energy,cgy_per_mu,effective_date
+Synergy2,6,1.000,2020-01-01
+Synergy2,6,1.012,2021-03-15
+Synergy2,12,0.995,2020-01-01
+Other,6,0.98,2019-01-01
+";
+        let table = parse_calibration_table(data.as_bytes()).unwrap();
+        let find = |machine, energy, d| {
+            find_calibration(&table.calibrations, machine, energy, d).map(|c| c.cgy_per_mu)
+        };
+        assert_eq!(find("Synergy2", 6.0, date(2021, 3, 14)), Some(1.0));
+        assert_eq!(find("Synergy2", 6.0, date(2021, 3, 15)), Some(1.012));
+        assert_eq!(find("Synergy2", 12.0, date(2024, 1, 1)), Some(0.995));
+        assert_eq!(find("Synergy2", 6.0, date(2019, 12, 31)), None);
+        assert_eq!(find("Synergy2", 8.0, date(2024, 1, 1)), None);
+        assert_eq!(table.machine("Synergy2").len(), 3);
+
+        let mut cd = CorrectionData::new();
+        cd.machine = "Synergy2".to_owned();
+        assert!(cd.get_calibration(8.0, date(2024, 1, 1)).unwrap().is_none());
+        cd.calibrations = table.machine("Synergy2");
+        assert!(matches!(
+            cd.get_calibration(8.0, date(2024, 1, 1)),
+            Err(EmuError::CalibrationNotFound(_, _, _))
+        ));
+        // 9 MeV lies halfway between the calibrations of 6 and 12 MeV valid on the date
+        cd.options.energy_interpolation = true;
+        let interpolated = cd.get_calibration(9.0, date(2021, 1, 1)).unwrap().unwrap();
+        assert!((interpolated.cgy_per_mu - 0.9975).abs() < 1e-12);
+        assert_eq!(interpolated.effective_date, date(2020, 1, 1));
+        let interpolated = cd.get_calibration(9.0, date(2024, 1, 1)).unwrap().unwrap();
+        assert!((interpolated.cgy_per_mu - 1.0035).abs() < 1e-12);
+        assert_eq!(interpolated.effective_date, date(2021, 3, 15));
+        assert!(cd.get_calibration(15.0, date(2024, 1, 1)).is_err());
+        assert!(cd.get_calibration(9.0, date(2019, 1, 1)).is_err());
+
+        let dir = std::env::temp_dir().join(format!(
+            "emu_check_calibration_round_trip_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tmp = dir.join("calibration.csv");
+        write_calibration_table(tmp.clone(), &table).unwrap();
+        assert_eq!(
+            read_calibration_table(tmp.clone()).unwrap().calibrations,
+            table.calibrations
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn calibration_invalid() {
+        let data = "machine,energy,cgy_per_mu,effective_date\nSynergy2,6,-1,2020-01-01\n";
+        assert!(parse_calibration_table(data.as_bytes()).is_err());
+        let data = "machine,energy,cgy_per_mu,effective_date\nSynergy2,6,1,2020-13-01\n";
+        assert!(parse_calibration_table(data.as_bytes()).is_err());
+        let data = "machine,energy,cgy_per_mu,effective_date\n\
+            Synergy2,6,1,2020-01-01\nSynergy2,6,1.01,2020-01-01\n";
+        assert!(parse_calibration_table(data.as_bytes()).is_err());
+        let data = "machine,energy,cgy_per_mu,effective_date\n\
+            Synergy2,6,1,2020-01-01\nSynergy2,6.0,1.01,2020-01-01\n";
+        assert!(parse_calibration_table(data.as_bytes()).is_err());
+    }
+}
@@ -1,11 +1,19 @@
+use crate::correction_factor::apply_factors;
 use crate::errors::EmuError;
 use crate::fda_table::FdaTable;
+use crate::ipol::{find_neighbours, interpolate_linear};
 use crate::of_table::OFTable;
-use crate::{read_fda_table, read_of_table};
+use crate::{
+    builtin_factors, load_calc_options, load_calibration_table, load_csv_format,
+    load_energy_tables, load_uncertainties, load_verified_manifest, read_dataset,
+    read_fda_table_with_format, read_of_table_with_format, CalcOptions, CalcParam, Calibration,
+    FdaFactor, OFInterpolation, Obliquity, ObliquityTable, Pdd, PddTable, Shield, ShieldTable,
+    SsdModel, Uncertainties,
+};
 use serde::{Deserialize, Serialize};
 
 use async_std::task;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CorrectionData {
@@ -13,6 +21,18 @@ pub struct CorrectionData {
     pub applicator: String,
     pub output_factors: OFTable,
     pub fda: FdaTable,
+    #[serde(default)]
+    pub options: CalcOptions,
+    #[serde(default, skip_serializing_if = "Uncertainties::is_empty")]
+    pub uncertainties: Uncertainties,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pdd: Option<PddTable>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub obliquity: Option<ObliquityTable>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shield: Option<ShieldTable>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub calibrations: Vec<Calibration>, // calibration history of the machine
 }
 
 impl CorrectionData {
@@ -22,6 +42,12 @@ impl CorrectionData {
             applicator: "".to_string(),
             output_factors: OFTable::new(),
             fda: FdaTable::new(),
+            options: CalcOptions::default(),
+            uncertainties: Uncertainties::default(),
+            pdd: None,
+            obliquity: None,
+            shield: None,
+            calibrations: vec![],
         }
     }
 
@@ -51,19 +77,76 @@ impl CorrectionData {
         self.fda.add(name, id, corrections)
     }
 
-    /// Compute the total correction factor: CF_OF * CF_fda
+    /// Compute the total correction factor of a beam at perpendicular incidence, without bolus
+    /// or internal shield: CF_OF * CF_fda * the built-in correction factors that apply.
+    /// See compute for the other beams.
     pub fn get_correction_factor(
         &self,
         energy: f64,
         ssd: f64,
         fda_id: usize,
     ) -> Result<f64, EmuError> {
-        let cf_of = self.output_factors.get_cf(energy, ssd)?;
-        let cf_fda = self.fda.get_cf(energy, fda_id)?;
-        let cf = cf_of * cf_fda;
+        let zref = self
+            .get_zref(energy)
+            .ok_or(EmuError::EnergyNotFound(energy))?;
+        let calc_param = CalcParam {
+            energy,
+            ssd,
+            depth_zref: zref,
+            fda_id,
+            ..CalcParam::new()
+        };
+        let cf_of = self.get_of_ipol(energy, ssd, zref)?.cf;
+        let cf_fda = self.get_fda_factor(&calc_param)?.cf;
+        let factors = apply_factors(&builtin_factors(), &calc_param, self)?;
+        let cf = cf_of * cf_fda * factors.iter().map(|f| f.value).product::<f64>();
         Ok(cf)
     }
 
+    /// Get the output factor correction using the SSD model of the calculation options.
+    pub fn get_of_ipol(
+        &self,
+        energy: f64,
+        ssd: f64,
+        zref: f64,
+    ) -> Result<OFInterpolation, EmuError> {
+        let energy_interpolation = self.options.energy_interpolation;
+        match self.options.ssd_model {
+            SsdModel::Tabulated => self.output_factors.get_cf_ipol_with(
+                energy,
+                ssd,
+                energy_interpolation,
+                self.options.ssd_interpolation_for(&self.machine),
+            ),
+            SsdModel::VirtualSource => self.output_factors.get_cf_virtual_source(
+                energy,
+                ssd,
+                zref,
+                energy_interpolation,
+                self.options.ssd_extrapolation,
+            ),
+        }
+    }
+
+    /// Get the correction factor of the FDA, or of the cutout if it's set in the calculation parameters.
+    pub fn get_fda_factor(&self, calc_param: &CalcParam) -> Result<FdaFactor, EmuError> {
+        let energy_interpolation = self.options.energy_interpolation;
+        match &calc_param.cutout {
+            Some(cutout) => self
+                .fda
+                .get_cf_cutout(calc_param.energy, cutout, energy_interpolation),
+            None => {
+                let cf = if energy_interpolation {
+                    self.fda
+                        .get_cf_ipol_energy(calc_param.energy, calc_param.fda_id)?
+                } else {
+                    self.fda.get_cf(calc_param.energy, calc_param.fda_id)?
+                };
+                Ok(FdaFactor { cf, derived: false })
+            }
+        }
+    }
+
     pub fn get_energies(&self) -> Vec<f64> {
         self.output_factors.energies.clone()
     }
@@ -73,13 +156,31 @@ impl CorrectionData {
     }
 
     // Get the zref depth in function of the energy.
+    // If energy interpolation is enabled, zref is interpolated for an energy that isn't tabulated.
     pub fn get_zref(&self, energy: f64) -> Option<f64> {
-        for (e, zr) in self.output_factors.energies.iter().zip(self.output_factors.zrefs.iter()) {
+        for (e, zr) in self
+            .output_factors
+            .energies
+            .iter()
+            .zip(self.output_factors.zrefs.iter())
+        {
             if *e == energy {
                 return Some(*zr);
             }
         }
-        None
+        if !self.options.energy_interpolation {
+            return None;
+        }
+        let (i0, i1) = find_neighbours(&self.output_factors.energies, energy)?;
+        let energies = &self.output_factors.energies;
+        let zrefs = &self.output_factors.zrefs;
+        Some(interpolate_linear(
+            energy,
+            energies[i0],
+            energies[i1],
+            *zrefs.get(i0)?,
+            *zrefs.get(i1)?,
+        ))
     }
 }
 
@@ -89,74 +190,7 @@ impl Default for CorrectionData {
     }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-
-    fn build_corr_table() -> CorrectionData {
-        let mut table = CorrectionData::new();
-        table.set_energies(vec![4.0, 6.0, 8.0, 10.0, 12.0]);
-        table.set_zrefs(vec![0.89, 1.36, 1.81, 2.31, 2.78]);
-        assert!(table
-            .add_output_factor_per_ssd(95.0, vec![0.865, 0.953, 0.994, 1.006, 1.037])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(95.5, vec![0.856, 0.945, 0.986, 0.995, 1.026])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(96.0, vec![0.843, 0.931, 0.973, 0.982, 1.011])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(97.0, vec![0.818, 0.902, 0.946, 0.957, 0.982])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(98.0, vec![0.792, 0.874, 0.919, 0.932, 0.953])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(99.0, vec![0.764, 0.846, 0.892, 0.906, 0.926])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(100.0, vec![0.736, 0.818, 0.865, 0.88, 0.899])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(105.0, vec![0.619, 0.704, 0.753, 0.775, 0.791])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(110.0, vec![0.526, 0.613, 0.663, 0.688, 0.706])
-            .is_ok());
-        assert!(table
-            .add_output_factor_per_ssd(115.0, vec![0.442, 0.533, 0.584, 0.614, 0.63])
-            .is_ok());
-
-        assert!(table
-            .add_field_defining_aperture("6x6", 1, vec![0.9, 0.8, 0.7, 0.6, 0.5])
-            .is_ok());
-        assert!(table
-            .add_field_defining_aperture("4x6", 3, vec![1.9, 1.8, 1.7, 1.6, 1.5])
-            .is_ok());
-        assert!(table
-            .add_field_defining_aperture("4x4", 10, vec![2.9, 2.8, 2.7, 2.6, 2.5])
-            .is_ok());
-        table
-    }
-
-    #[test]
-    fn correction_data_get_cf() {
-        let table = build_corr_table();
-        assert!(table.get_correction_factor(12.0, 103.0, 3).is_ok());
-        assert!(
-            (table.get_correction_factor(12.0, 103.0, 3).unwrap() - 1.2513) < std::f64::EPSILON
-        );
-        assert!((table.get_correction_factor(10.0, 96.7, 3).unwrap() - 1.5432) < std::f64::EPSILON);
-
-        assert!(table.get_correction_factor(11.0, 95.0, 3).is_err());
-        assert!(table.get_correction_factor(12.0, 94.9, 3).is_err());
-        assert!(table.get_correction_factor(12.0, 115.1, 3).is_err());
-        assert!(table.get_correction_factor(12.0, 115.0, 4).is_err());
-    }
-}
-
-fn get_list_data_files(dirname: &str) -> Result<(Vec<PathBuf>, Vec<PathBuf>), EmuError> {
+pub(crate) fn get_list_data_files(dirname: &str) -> Result<(Vec<PathBuf>, Vec<PathBuf>), EmuError> {
     let dir = PathBuf::from(dirname);
     if !dir.is_dir() {
         return Err(EmuError::DirNotFound(dir));
@@ -183,10 +217,35 @@ fn get_list_data_files(dirname: &str) -> Result<(Vec<PathBuf>, Vec<PathBuf>), Em
     Ok((vof, vfda))
 }
 
+// Correction data of a machine and applicator, read from a file with additional data.
+pub(crate) fn find_correction_data<'a>(
+    vcd: &'a mut [CorrectionData],
+    machine: &str,
+    applicator: &str,
+    path_buf: &Path,
+) -> Result<&'a mut CorrectionData, EmuError> {
+    vcd.iter_mut()
+        .find(|cd| cd.machine == machine && cd.applicator == applicator)
+        .ok_or_else(|| {
+            EmuError::Format(format!(
+                "{:#?}: No correction data for machine [{}] and applicator [{}]",
+                path_buf, machine, applicator
+            ))
+        })
+}
+
 /// Load the configuration data (outputfactors and field defining apertures)
 /// and process the data into a vector of CorrectionData.
+/// The configuration data is read from a JSON or TOML dataset file if dirname is a file.
 pub async fn load_data(dirname: &str) -> Result<Vec<CorrectionData>, EmuError> {
+    if Path::new(dirname).is_file() {
+        return Ok(read_dataset(PathBuf::from(dirname))?.data().clone());
+    }
+    // Refuse to use data that differs from the approved version.
+    load_verified_manifest(dirname)?;
     let (vof, vfda) = get_list_data_files(dirname)?;
+    let opt_format = load_csv_format(dirname)?;
+    let options = load_calc_options(dirname)?;
     let nvof = vof.len();
     let nvfda = vfda.len();
 
@@ -208,12 +267,16 @@ pub async fn load_data(dirname: &str) -> Result<Vec<CorrectionData>, EmuError> {
     // proccessed accordingly.
     for pb in vof {
         let tpb = pb.clone();
-        thandles_of.push(task::spawn(async move { read_of_table(tpb) }));
+        thandles_of.push(task::spawn(async move {
+            read_of_table_with_format(tpb, opt_format)
+        }));
     }
 
     for pb in vfda {
         let tpb = pb.clone();
-        thandles_fda.push(task::spawn(async move { read_fda_table(tpb) }));
+        thandles_fda.push(task::spawn(async move {
+            read_fda_table_with_format(tpb, opt_format)
+        }));
     }
 
     // The for loop takes ownership and waits for the result
@@ -233,6 +296,7 @@ pub async fn load_data(dirname: &str) -> Result<Vec<CorrectionData>, EmuError> {
             cd.machine = machine.clone();
             cd.applicator = applicator.clone();
             cd.output_factors = of_table.clone();
+            cd.options = options.clone();
         }
         for j in 0..nvfda {
             let (machine, applicator, fda_table) = vfda_tables.get(j).unwrap();
@@ -256,6 +320,98 @@ pub async fn load_data(dirname: &str) -> Result<Vec<CorrectionData>, EmuError> {
     if vcd.is_empty() {
         return Err(EmuError::IO("No configuration data was loaded.".to_owned()));
     }
+    load_uncertainties(dirname, opt_format, &mut vcd)?;
+    load_energy_tables::<Pdd>(dirname, opt_format, &mut vcd)?;
+    load_energy_tables::<Obliquity>(dirname, opt_format, &mut vcd)?;
+    load_energy_tables::<Shield>(dirname, opt_format, &mut vcd)?;
+    let calibrations = load_calibration_table(dirname)?;
+    for cd in vcd.iter_mut() {
+        cd.calibrations = calibrations.machine(&cd.machine);
+    }
 
     Ok(vcd)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn build_corr_table() -> CorrectionData {
+        let mut table = CorrectionData::new();
+        table.set_energies(vec![4.0, 6.0, 8.0, 10.0, 12.0]);
+        table.set_zrefs(vec![0.89, 1.36, 1.81, 2.31, 2.78]);
+        assert!(table
+            .add_output_factor_per_ssd(95.0, vec![0.865, 0.953, 0.994, 1.006, 1.037])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(95.5, vec![0.856, 0.945, 0.986, 0.995, 1.026])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(96.0, vec![0.843, 0.931, 0.973, 0.982, 1.011])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(97.0, vec![0.818, 0.902, 0.946, 0.957, 0.982])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(98.0, vec![0.792, 0.874, 0.919, 0.932, 0.953])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(99.0, vec![0.764, 0.846, 0.892, 0.906, 0.926])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(100.0, vec![0.736, 0.818, 0.865, 0.88, 0.899])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(105.0, vec![0.619, 0.704, 0.753, 0.775, 0.791])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(110.0, vec![0.526, 0.613, 0.663, 0.688, 0.706])
+            .is_ok());
+        assert!(table
+            .add_output_factor_per_ssd(115.0, vec![0.442, 0.533, 0.584, 0.614, 0.63])
+            .is_ok());
+
+        assert!(table
+            .add_field_defining_aperture("6x6", 1, vec![0.9, 0.8, 0.7, 0.6, 0.5])
+            .is_ok());
+        assert!(table
+            .add_field_defining_aperture("4x6", 3, vec![1.9, 1.8, 1.7, 1.6, 1.5])
+            .is_ok());
+        assert!(table
+            .add_field_defining_aperture("4x4", 10, vec![2.9, 2.8, 2.7, 2.6, 2.5])
+            .is_ok());
+        table
+    }
+
+    #[test]
+    fn correction_data_energy_interpolation() {
+        let mut table = build_corr_table();
+        assert!(table.get_zref(5.0).is_none());
+        assert!(table.get_correction_factor(11.0, 103.0, 3).is_err());
+
+        table.options.energy_interpolation = true;
+        assert!((table.get_zref(5.0).unwrap() - 1.125).abs() < 1e-12);
+        assert_eq!(table.get_zref(4.0), Some(0.89));
+        let cf10 = table.get_correction_factor(10.0, 103.0, 3).unwrap();
+        let cf12 = table.get_correction_factor(12.0, 103.0, 3).unwrap();
+        let cf11 = table.get_correction_factor(11.0, 103.0, 3).unwrap();
+        assert!(cf11 > cf12.min(cf10) && cf11 < cf12.max(cf10));
+        assert!(table.get_correction_factor(13.0, 103.0, 3).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::float_equality_without_abs, clippy::legacy_numeric_constants)]
+    fn correction_data_get_cf() {
+        let table = build_corr_table();
+        assert!(table.get_correction_factor(12.0, 103.0, 3).is_ok());
+        assert!(
+            (table.get_correction_factor(12.0, 103.0, 3).unwrap() - 1.2513) < std::f64::EPSILON
+        );
+        assert!((table.get_correction_factor(10.0, 96.7, 3).unwrap() - 1.5432) < std::f64::EPSILON);
+
+        assert!(table.get_correction_factor(11.0, 95.0, 3).is_err());
+        assert!(table.get_correction_factor(12.0, 94.9, 3).is_err());
+        assert!(table.get_correction_factor(12.0, 115.1, 3).is_err());
+        assert!(table.get_correction_factor(12.0, 115.0, 4).is_err());
+    }
+}
@@ -1,9 +1,16 @@
-use crate::{load_data, CorrectionData, EmuError};
+use crate::{
+    compute_with_factors, load_data, resolve_fda, AppliedFactor, BolusAdjustment, CalcParam,
+    Calibration, CorrectionData, CorrectionFactor, EmuError, InterpolationMethod, MuUncertainty,
+    OFInterpolation, PddConversion,
+};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CorrectionDataSet {
     data: Vec<CorrectionData>,
+    #[serde(skip)]
+    factors: Vec<Arc<dyn CorrectionFactor>>, // applied after the built-in factors
 }
 
 impl Default for CorrectionDataSet {
@@ -14,19 +21,45 @@ impl Default for CorrectionDataSet {
 
 impl From<Vec<CorrectionData>> for CorrectionDataSet {
     fn from(v: Vec<CorrectionData>) -> Self {
-        Self { data: v }
+        Self {
+            data: v,
+            factors: vec![],
+        }
     }
 }
 
 impl From<&Vec<CorrectionData>> for CorrectionDataSet {
     fn from(v: &Vec<CorrectionData>) -> Self {
-        Self { data: v.clone() }
+        Self {
+            data: v.clone(),
+            factors: vec![],
+        }
     }
 }
 
 impl CorrectionDataSet {
     pub fn new() -> Self {
-        Self { data: vec![] }
+        Self {
+            data: vec![],
+            factors: vec![],
+        }
+    }
+
+    pub fn data(&self) -> &Vec<CorrectionData> {
+        &self.data
+    }
+
+    /// Register a correction factor that is applied in every calculation with this dataset
+    /// (compute, calc and predict_dose), after the built-in factors (see builtin_factors).
+    /// The free functions (compute, calculate_mu), the batch, the RT plan and the command line
+    /// only apply the built-in factors.
+    pub fn register_factor(&mut self, factor: Arc<dyn CorrectionFactor>) {
+        self.factors.push(factor);
+    }
+
+    /// Registered correction factors.
+    pub fn factors(&self) -> &[Arc<dyn CorrectionFactor>] {
+        &self.factors
     }
 
     pub fn get_machines(&self) -> Vec<String> {
@@ -108,21 +141,171 @@ impl CorrectionDataSet {
         None
     }
 
+    /// Get the correction data of a machine and applicator.
+    pub fn get(&self, machine: &str, applicator: &str) -> Result<&CorrectionData, EmuError> {
+        if !self.data.iter().any(|cd| cd.machine == machine) {
+            return Err(EmuError::MachineNotFound(machine.to_owned()));
+        }
+        self.data
+            .iter()
+            .find(|cd| cd.machine == machine && cd.applicator == applicator)
+            .ok_or_else(|| {
+                EmuError::CorrectionDataNotFound(machine.to_owned(), applicator.to_owned())
+            })
+    }
+
+    /// Compute the MUs for the calculation parameters.
+    pub fn compute(&self, calc_param: &CalcParam) -> Result<Computed, EmuError> {
+        let cd = self.get(&calc_param.machine, &calc_param.applicator)?;
+        compute_with_factors(calc_param, cd, &self.factors)
+    }
+
+    /// Parse and validate the calculation parameters and compute the MUs.
+    /// The applicator fitment is the name of the field defining aperture, a cutout that isn't
+    /// in the FDA table is calculated with compute and CalcParam::cutout.
+    #[allow(clippy::too_many_arguments)]
     pub fn calc<S: AsRef<str> + ?Sized>(
+        &self,
         machine: &S,
         applicator: &S,
         applicator_fitment: &S,
         energy: &S,
         ssd: &S,
         planned_beam_mu: &S,
-        dose_zref: &S
-    ) {
-        //
+        dose_zref: &S,
+    ) -> Result<Computed, EmuError> {
+        let machine = machine.as_ref().trim();
+        let applicator = applicator.as_ref().trim();
+        let cd = self.get(machine, applicator)?;
+        let energy = parse_positive("energy", energy.as_ref())?;
+        let depth_zref = cd
+            .get_zref(energy)
+            .ok_or(EmuError::EnergyNotFound(energy))?;
+        let fda_id = resolve_fda(&cd.fda, applicator_fitment.as_ref().trim())?;
+        let calc_param = CalcParam {
+            machine: machine.to_owned(),
+            applicator: applicator.to_owned(),
+            energy,
+            ssd: parse_positive("ssd", ssd.as_ref())?,
+            depth_zref,
+            dose_zref: parse_positive("dose_zref", dose_zref.as_ref())?,
+            planned_beam_mu: parse_positive("planned_beam_mu", planned_beam_mu.as_ref())?,
+            fda_id,
+            ..CalcParam::new()
+        };
+        compute_with_factors(&calc_param, cd, &self.factors)
     }
+
+    /// Predict the dose at zref for the planned MUs and compare it with the planned dose,
+    /// applying the registered correction factors.
+    pub fn predict_dose(&self, calc_param: &CalcParam) -> Result<DosePrediction, EmuError> {
+        self.compute(calc_param)?.dose_prediction()
+    }
+}
+
+// Parse a strictly positive, finite number.
+fn parse_positive(field: &str, value: &str) -> Result<f64, EmuError> {
+    match value.trim().parse::<f64>() {
+        Ok(v) if v.is_finite() && v > 0.0 => Ok(v),
+        _ => Err(EmuError::InvalidInput(field.to_owned(), value.to_owned())),
+    }
+}
+
+/// Result of an MU check calculation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Computed {
+    pub calc_param: CalcParam,
+    pub cf_of: f64,           // output factor correction
+    pub cf_fda: f64,          // field defining aperture correction
+    pub cf_fda_derived: bool, // cutout factor derived from the FDA table instead of measured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub factors: Vec<AppliedFactor>, // built-in and registered correction factors that were applied
+    pub of_ipol: OFInterpolation,
+    pub mu: f64,
+    pub difference: f64, // relative difference [%] between the planned and the computed MUs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub interpolation_comparison: Vec<InterpolationComparison>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncertainty: Option<MuUncertainty>, // None if the dataset has no uncertainties
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pdd_conversion: Option<PddConversion>, // Some if the dose was prescribed at another depth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calibration: Option<Calibration>, // None if the machine has no calibrations (1 cGy/MU)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bolus: Option<BolusAdjustment>, // Some if the beam is treated with bolus
 }
 
-#[derive(Debug, Clone)]
-pub struct Computed {}
+impl Computed {
+    /// Value of the applied correction factor with the name, None if it wasn't applied.
+    pub fn factor(&self, name: &str) -> Option<f64> {
+        self.factors
+            .iter()
+            .find(|f| f.name == name)
+            .map(|f| f.value)
+    }
+
+    /// Dose [cGy] at zref per MU according to the tables: cGy/MU · CF_OF · CF_FDA · factors.
+    pub fn dose_per_mu(&self) -> f64 {
+        let cgy_per_mu = self.calibration.as_ref().map_or(1.0, |c| c.cgy_per_mu);
+        cgy_per_mu
+            * self.cf_of
+            * self.cf_fda
+            * self.factors.iter().map(|f| f.value).product::<f64>()
+    }
+
+    /// Dose [cGy] at zref predicted by the tables for the planned MUs.
+    pub fn predicted_dose(&self) -> Result<f64, EmuError> {
+        if !self.calc_param.has_planned_beam_mu() {
+            return Err(EmuError::MissingInput("planned_beam_mu".to_owned()));
+        }
+        Ok(self.calc_param.planned_beam_mu * self.dose_per_mu())
+    }
+
+    /// Dose at zref predicted by the tables for the planned MUs and its deviation from the
+    /// planned dose, which must be given.
+    pub fn dose_prediction(&self) -> Result<DosePrediction, EmuError> {
+        let predicted_dose = self.predicted_dose()?;
+        if !self.calc_param.has_dose_zref() {
+            return Err(EmuError::MissingInput("dose_zref".to_owned()));
+        }
+        let planned_dose = self.calc_param.dose_zref;
+        let deviation = predicted_dose - planned_dose;
+        Ok(DosePrediction {
+            planned_dose,
+            predicted_dose,
+            deviation,
+            relative_deviation: deviation / planned_dose * 100.0,
+        })
+    }
+}
+
+/// Dose at zref predicted from the planned MUs and its deviation from the planned dose.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DosePrediction {
+    pub planned_dose: f64,       // dose [cGy] at zref of the plan
+    pub predicted_dose: f64,     // dose [cGy] at zref delivered by the planned MUs
+    pub deviation: f64,          // predicted - planned dose [cGy]
+    pub relative_deviation: f64, // deviation relative to the planned dose [%]
+}
+
+impl std::fmt::Display for DosePrediction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2} cGy (planned: {:.2} cGy, deviation: {:+.2} cGy, {:+.3}%)",
+            self.predicted_dose, self.planned_dose, self.deviation, self.relative_deviation
+        )
+    }
+}
+
+/// MUs computed with another method to interpolate the output factors between SSDs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InterpolationComparison {
+    pub method: InterpolationMethod,
+    pub cf_of: f64,
+    pub mu: f64,
+    pub difference: f64, // relative difference [%] with the MUs of the selected method
+}
 
 /// Load the configuration data (outputfactors and field defining apertures)
 /// and process the data into a CorrectionDataSet.
@@ -130,3 +313,129 @@ pub async fn correction_data_set_load_data(dirname: &str) -> Result<CorrectionDa
     let res = load_data(dirname).await?;
     Ok(CorrectionDataSet::from(res))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Cutout;
+
+    #[test]
+    fn correction_data_set_calc() {
+        let cds = async_std::task::block_on(correction_data_set_load_data("resources")).unwrap();
+        let computed = cds
+            .calc(
+                "Synergy2",
+                "10x10",
+                "2.5x10",
+                "12",
+                "99.2",
+                "118.046388925549",
+                "100",
+            )
+            .unwrap();
+        assert_eq!(computed.calc_param.fda_id, 9);
+        assert_eq!(computed.calc_param.depth_zref, 2.78);
+        assert_eq!(computed.cf_fda, 0.917);
+        assert!(!computed.cf_fda_derived);
+        assert_eq!(computed.of_ipol.ssd0, 99.0);
+        assert_eq!(computed.of_ipol.ssd1, 100.0);
+        assert_eq!(computed.of_ipol.of0, 0.928);
+        assert_eq!(computed.of_ipol.of1, 0.907);
+        assert!((computed.mu - 118.046388925549).abs() < f32::EPSILON as f64);
+        assert!(computed.difference.abs() < 1e-6);
+
+        // a rectangle that isn't tabulated is only a cutout if it's asked for
+        assert!(matches!(
+            cds.calc("Synergy2", "10x10", "7x9", "12", "99.2", "118", "100"),
+            Err(EmuError::FdaNameNotFound(_))
+        ));
+        let mut cp = computed.calc_param.clone();
+        cp.fda_id = usize::MAX;
+        cp.cutout = Cutout::parse_rectangle("7x9");
+        let computed = cds.compute(&cp).unwrap();
+        assert!(computed.cf_fda_derived);
+        assert!((computed.cf_fda - 1.0).abs() < 1e-9);
+
+        assert!(matches!(
+            cds.calc("Other", "10x10", "2.5x10", "12", "99.2", "118", "100"),
+            Err(EmuError::MachineNotFound(_))
+        ));
+        assert!(matches!(
+            cds.calc("Synergy2", "5x5", "2.5x10", "12", "99.2", "118", "100"),
+            Err(EmuError::CorrectionDataNotFound(_, _))
+        ));
+        assert!(matches!(
+            cds.calc("Synergy2", "10x10", "Circle 5", "12", "99.2", "118", "100"),
+            Err(EmuError::FdaNameNotFound(_))
+        ));
+        // the factor of a cutout can't be derived outside the range of the FDA table
+        cp.cutout = Cutout::parse_rectangle("1x1");
+        assert!(matches!(cds.compute(&cp), Err(EmuError::Cutout(_))));
+        assert!(matches!(
+            cds.calc("Synergy2", "10x10", "2.5x10", "11", "99.2", "118", "100"),
+            Err(EmuError::EnergyNotFound(_))
+        ));
+        assert!(matches!(
+            cds.calc("Synergy2", "10x10", "2.5x10", "12", "-99.2", "118", "100"),
+            Err(EmuError::InvalidInput(_, _))
+        ));
+        assert!(matches!(
+            cds.calc("Synergy2", "10x10", "2.5x10", "12", "99.2", "118", "abc"),
+            Err(EmuError::InvalidInput(_, _))
+        ));
+    }
+
+    // Site specific factor for the 6 MeV beams.
+    #[derive(Debug)]
+    struct SiteFactor;
+
+    impl CorrectionFactor for SiteFactor {
+        fn name(&self) -> &str {
+            "site 6 MeV"
+        }
+
+        fn evaluate(
+            &self,
+            calc_param: &CalcParam,
+            _cd: &CorrectionData,
+        ) -> Result<Option<crate::FactorValue>, EmuError> {
+            if calc_param.energy != 6.0 {
+                return Ok(None);
+            }
+            Ok(Some(
+                crate::FactorValue::new(0.98).with_input("energy", calc_param.energy),
+            ))
+        }
+    }
+
+    #[test]
+    fn correction_data_set_registered_factor() {
+        let mut cds =
+            async_std::task::block_on(correction_data_set_load_data("resources")).unwrap();
+        let args = ["Synergy2", "10x10", "10x10", "6", "95", "100", "100"];
+        let calc = |cds: &CorrectionDataSet, energy: &str| {
+            cds.calc(args[0], args[1], args[2], energy, args[4], args[5], args[6])
+                .unwrap()
+        };
+        let plain = calc(&cds, "6");
+        assert!(plain.factors.is_empty());
+        assert!(cds.factors().is_empty());
+
+        cds.register_factor(Arc::new(SiteFactor));
+        assert_eq!(cds.factors().len(), 1);
+        let computed = calc(&cds, "6");
+        assert_eq!(computed.factors.len(), 1);
+        let factor = &computed.factors[0];
+        assert_eq!(factor.name, "site 6 MeV");
+        assert_eq!(factor.value, 0.98);
+        assert_eq!(factor.inputs["energy"], "6");
+        assert_eq!(factor.to_string(), "site 6 MeV: 0.9800 [energy: 6]");
+        assert!((computed.mu * 0.98 - plain.mu).abs() < 1e-9);
+        // the registered factor is applied to the predicted dose too
+        let prediction = cds.predict_dose(&computed.calc_param).unwrap();
+        let plain_dose = plain.predicted_dose().unwrap();
+        assert!((prediction.predicted_dose - 0.98 * plain_dose).abs() < 1e-9);
+        // the factor doesn't apply to the other energies
+        assert!(calc(&cds, "12").factors.is_empty());
+    }
+}
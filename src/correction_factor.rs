@@ -0,0 +1,141 @@
+use crate::{CalcParam, CorrectionData, EmuError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Value of a correction factor and the inputs it was derived from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FactorValue {
+    pub value: f64,
+    pub inputs: BTreeMap<String, String>, // input name -> value
+}
+
+impl FactorValue {
+    pub fn new(value: f64) -> Self {
+        Self {
+            value,
+            inputs: BTreeMap::new(),
+        }
+    }
+
+    /// Add an input used to derive the factor.
+    pub fn with_input<T: std::fmt::Display>(mut self, name: &str, value: T) -> Self {
+        self.inputs.insert(name.to_owned(), value.to_string());
+        self
+    }
+}
+
+/// Correction factor applied in addition to the output factor and the FDA factor:
+/// MU = D(zref) / (cGy/MU · CF_OF · CF_FDA · ... · factor).
+/// The obliquity and shield corrections are built-in factors, see builtin_factors.
+///
+/// The calculation parameters passed to the factor have the dose at zref and the calculation
+/// date resolved. The SSD is the SSD to the skin, see CalcParam::bolus_adjustment for the SSD
+/// to the bolus surface.
+pub trait CorrectionFactor: std::fmt::Debug + Send + Sync {
+    /// Name of the factor in the result.
+    fn name(&self) -> &str;
+
+    /// Value of the factor, None if it doesn't apply to the calculation.
+    fn evaluate(
+        &self,
+        calc_param: &CalcParam,
+        cd: &CorrectionData,
+    ) -> Result<Option<FactorValue>, EmuError>;
+}
+
+/// Correction factor that was applied in a calculation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppliedFactor {
+    pub name: String,
+    pub value: f64,
+    pub inputs: BTreeMap<String, String>,
+}
+
+impl std::fmt::Display for AppliedFactor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {:.4}", self.name, self.value)?;
+        if !self.inputs.is_empty() {
+            let inputs: Vec<String> = self
+                .inputs
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect();
+            write!(f, " [{}]", inputs.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Obliquity correction for the angle of incidence, see CorrectionData::get_obliquity_factor.
+#[derive(Debug, Clone, Copy)]
+pub struct ObliquityFactor;
+
+impl CorrectionFactor for ObliquityFactor {
+    fn name(&self) -> &str {
+        "obliquity"
+    }
+
+    fn evaluate(
+        &self,
+        calc_param: &CalcParam,
+        cd: &CorrectionData,
+    ) -> Result<Option<FactorValue>, EmuError> {
+        Ok(cd.get_obliquity_factor(calc_param)?.map(|cf| {
+            FactorValue::new(cf).with_input("angle", calc_param.incidence_angle.unwrap_or(0.0))
+        }))
+    }
+}
+
+/// Backscatter of an internal shield, see CorrectionData::get_shield_factor.
+#[derive(Debug, Clone, Copy)]
+pub struct ShieldFactor;
+
+impl CorrectionFactor for ShieldFactor {
+    fn name(&self) -> &str {
+        "shield"
+    }
+
+    fn evaluate(
+        &self,
+        calc_param: &CalcParam,
+        cd: &CorrectionData,
+    ) -> Result<Option<FactorValue>, EmuError> {
+        Ok(cd.get_shield_factor(calc_param)?.map(|cf| {
+            FactorValue::new(cf).with_input("depth", calc_param.shield_depth.unwrap_or(0.0))
+        }))
+    }
+}
+
+/// Correction factors applied in every calculation (obliquity and internal shield), before the
+/// factors registered on a CorrectionDataSet.
+/// The bolus isn't a factor, the output factor is looked up at the SSD to the bolus surface.
+pub fn builtin_factors() -> Vec<Arc<dyn CorrectionFactor>> {
+    vec![Arc::new(ObliquityFactor), Arc::new(ShieldFactor)]
+}
+
+// Evaluate the registered factors that apply to the calculation.
+pub(crate) fn apply_factors(
+    factors: &[Arc<dyn CorrectionFactor>],
+    calc_param: &CalcParam,
+    cd: &CorrectionData,
+) -> Result<Vec<AppliedFactor>, EmuError> {
+    let mut v = vec![];
+    for factor in factors {
+        if let Some(fv) = factor.evaluate(calc_param, cd)? {
+            if !(fv.value.is_finite() && fv.value > 0.0) {
+                return Err(EmuError::Logic(format!(
+                    "Correction factor [{}] must be a positive number [{}]",
+                    factor.name(),
+                    fv.value
+                )));
+            }
+            v.push(AppliedFactor {
+                name: factor.name().to_owned(),
+                value: fv.value,
+                inputs: fv.inputs,
+            });
+        }
+    }
+    Ok(v)
+}
@@ -0,0 +1,319 @@
+use crate::EmuError;
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Name of the file in the directory with the configuration data that sets the CSV format.
+pub const CSV_FORMAT_FILE_NAME: &str = "csv_format.json";
+
+/// Delimiter and decimal separator of the CSV files with the configuration data.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CsvFormat {
+    pub delimiter: char,
+    pub decimal_separator: char,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            decimal_separator: '.',
+        }
+    }
+}
+
+impl CsvFormat {
+    /// Number of header rows (machine, applicator and energy) used to detect the format.
+    const DETECT_ROWS: usize = 3;
+
+    /// Detect the format from the content of a CSV file.
+    /// Spreadsheets exported with a locale that uses a decimal comma separate the values by a
+    /// semicolon. The delimiter is the one that occurs most in the header rows, the values
+    /// below the header can't be used because they contain the decimal commas.
+    /// A semicolon delimited file is read with decimal commas.
+    pub fn detect(content: &str) -> Self {
+        let (nsemicolons, ncommas) = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .take(Self::DETECT_ROWS)
+            .fold((0, 0), |(ns, nc), line| {
+                (
+                    ns + line.matches(';').count(),
+                    nc + line.matches(',').count(),
+                )
+            });
+        if nsemicolons > ncommas {
+            Self {
+                delimiter: ';',
+                decimal_separator: ',',
+            }
+        } else {
+            Self::default()
+        }
+    }
+
+    /// Detect the format of a CSV file without header rows from the number of columns.
+    /// The file is semicolon delimited (with decimal commas) if every row has that number of
+    /// columns when split by semicolons, e.g. `-2,5;-3,5` for 2 columns.
+    pub fn detect_columns(content: &str, ncolumns: usize) -> Self {
+        let mut lines = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .peekable();
+        let has_lines = lines.peek().is_some();
+        if has_lines && lines.all(|line| line.split(';').count() == ncolumns) {
+            Self {
+                delimiter: ';',
+                decimal_separator: ',',
+            }
+        } else {
+            Self::default()
+        }
+    }
+
+    fn validate(&self) -> Result<(), EmuError> {
+        if !self.delimiter.is_ascii() || self.delimiter == self.decimal_separator {
+            return Err(EmuError::Format(format!(
+                "Invalid CSV format [delimiter: {}, decimal separator: {}]",
+                self.delimiter, self.decimal_separator
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Read the CSV format of the configuration data.
+pub fn read_csv_format(path_buf: PathBuf) -> Result<CsvFormat, EmuError> {
+    let content = std::fs::read_to_string(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    let format: CsvFormat = serde_json::from_str(&content)
+        .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+    format
+        .validate()
+        .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+    Ok(format)
+}
+
+/// Load the CSV format set for the configuration data.
+/// If the directory has no CSV format file, the format is detected for each file.
+pub fn load_csv_format(dirname: &str) -> Result<Option<CsvFormat>, EmuError> {
+    let path_buf = Path::new(dirname).join(CSV_FORMAT_FILE_NAME);
+    if path_buf.is_file() {
+        Ok(Some(read_csv_format(path_buf)?))
+    } else {
+        Ok(None)
+    }
+}
+
+// Open a CSV file without header using the given format, or the format detected from its content.
+pub(crate) fn csv_reader(
+    path_buf: &Path,
+    opt_format: Option<CsvFormat>,
+) -> Result<(csv::Reader<std::io::Cursor<String>>, CsvFormat), EmuError> {
+    let content = std::fs::read_to_string(path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    let format = opt_format.unwrap_or_else(|| CsvFormat::detect(&content));
+    format.validate()?;
+    let rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(format.delimiter as u8)
+        .from_reader(std::io::Cursor::new(content));
+    Ok((rdr, format))
+}
+
+// Write the rows to a CSV file without header.
+pub(crate) fn write_rows(
+    path_buf: &Path,
+    rows: &[Vec<String>],
+    format: &CsvFormat,
+) -> Result<(), EmuError> {
+    format.validate()?;
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .delimiter(format.delimiter as u8)
+        .from_path(path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    for row in rows {
+        wtr.write_record(row)
+            .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    }
+    wtr.flush()
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    Ok(())
+}
+
+// A table is written with one column per energy, without energies the rows have no common length.
+pub(crate) fn check_energies(path_buf: &Path, energies: &[f64]) -> Result<(), EmuError> {
+    if energies.is_empty() {
+        return Err(EmuError::Format(format!(
+            "{:#?}: A table without energies can't be written",
+            path_buf
+        )));
+    }
+    Ok(())
+}
+
+// Format a number with the decimal separator of the CSV format.
+pub(crate) fn format_number(v: f64, format: &CsvFormat) -> String {
+    let s = v.to_string();
+    if format.decimal_separator == '.' {
+        s
+    } else {
+        s.replace('.', &format.decimal_separator.to_string())
+    }
+}
+
+// Row starting with a label and value, padded with empty cells up to the number of columns.
+pub(crate) fn label_row(label: &str, value: &str, ncolumns: usize) -> Vec<String> {
+    let mut row = vec![label.to_owned(), value.to_owned()];
+    row.resize(ncolumns.max(2), "".to_owned());
+    row
+}
+
+// 1-based line number of a record in the CSV file.
+pub(crate) fn record_row(record: &StringRecord) -> usize {
+    record.position().map_or(0, |p| p.line() as usize)
+}
+
+// Error for a cell (0-based column index) that doesn't contain the expected value.
+pub(crate) fn parse_error(
+    path: &Path,
+    record: &StringRecord,
+    column: usize,
+    expected: &str,
+) -> EmuError {
+    EmuError::Parse {
+        path: path.to_path_buf(),
+        row: record_row(record),
+        column: column + 1,
+        cell: record.get(column).unwrap_or("").to_owned(),
+        expected: expected.to_owned(),
+    }
+}
+
+// Parse the cell at a 0-based column index.
+pub(crate) fn parse_cell<T: FromStr>(
+    path: &Path,
+    record: &StringRecord,
+    column: usize,
+    expected: &str,
+    format: &CsvFormat,
+) -> Result<T, EmuError> {
+    let cell = record.get(column).unwrap_or("");
+    let res = if format.decimal_separator == '.' {
+        cell.parse::<T>()
+    } else {
+        cell.replace(format.decimal_separator, ".").parse::<T>()
+    };
+    res.map_err(|_| parse_error(path, record, column, expected))
+}
+
+// Parse the cells from a 0-based column index up to the end of the record.
+pub(crate) fn parse_cells<T: FromStr>(
+    path: &Path,
+    record: &StringRecord,
+    start: usize,
+    expected: &str,
+    format: &CsvFormat,
+) -> Result<Vec<T>, EmuError> {
+    (start..record.len())
+        .map(|j| parse_cell(path, record, j, expected, format))
+        .collect()
+}
+
+// Check that the cell at a 0-based column index contains a label.
+pub(crate) fn expect_label(
+    path: &Path,
+    record: &StringRecord,
+    column: usize,
+    label: &str,
+) -> Result<(), EmuError> {
+    if record.get(column) == Some(label) {
+        Ok(())
+    } else {
+        Err(parse_error(
+            path,
+            record,
+            column,
+            &format!("the label '{}'", label),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_cell_error() {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader("Energy,4,6,8\n95,1.0,1,O\n".as_bytes());
+        let records: Vec<StringRecord> = rdr.records().map(|r| r.unwrap()).collect();
+        let path = Path::new("of.csv");
+        assert!(expect_label(path, &records[0], 0, "Energy").is_ok());
+        let format = CsvFormat::default();
+        assert_eq!(
+            parse_cells::<f64>(path, &records[0], 1, "a number", &format).unwrap(),
+            vec![4.0, 6.0, 8.0]
+        );
+        match parse_cells::<f64>(path, &records[1], 1, "a number (output factor)", &format) {
+            Err(EmuError::Parse {
+                path,
+                row,
+                column,
+                cell,
+                expected,
+            }) => {
+                assert_eq!(path, Path::new("of.csv"));
+                assert_eq!(row, 2);
+                assert_eq!(column, 4);
+                assert_eq!(cell, "O");
+                assert_eq!(expected, "a number (output factor)");
+            }
+            _ => panic!("Expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn detect_format_columns() {
+        let semicolon = CsvFormat {
+            delimiter: ';',
+            decimal_separator: ',',
+        };
+        assert_eq!(
+            CsvFormat::detect_columns("-2,5;-3,5\n2,5;-3\n0;4,5\n", 2),
+            semicolon
+        );
+        assert_eq!(CsvFormat::detect_columns("-2;-3\n2;-3\n", 2), semicolon);
+        assert_eq!(
+            CsvFormat::detect_columns("-2.5,-3.5\n2.5,-3\n", 2),
+            CsvFormat::default()
+        );
+        assert_eq!(CsvFormat::detect_columns("", 2), CsvFormat::default());
+    }
+
+    #[test]
+    fn detect_format() {
+        let format = CsvFormat::detect("Energy,4,6\n95,1.000,0.995\n");
+        assert_eq!(format, CsvFormat::default());
+        let format =
+            CsvFormat::detect("Synergy2,,\nApplicator,10x10;6x6,\nEnergy,4,6\n95,1.0,0.9\n");
+        assert_eq!(format, CsvFormat::default());
+        let format = CsvFormat::detect("Energy;4;6\n95;1,000;0,995\n95,5;0,995;0,990\n");
+        assert_eq!(format.delimiter, ';');
+        assert_eq!(format.decimal_separator, ',');
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b';')
+            .from_reader("95;1,000;0,995\n".as_bytes());
+        let record = rdr.records().next().unwrap().unwrap();
+        let path = Path::new("of.csv");
+        assert_eq!(
+            parse_cells::<f64>(path, &record, 0, "a number", &format).unwrap(),
+            vec![95.0, 1.0, 0.995]
+        );
+    }
+}
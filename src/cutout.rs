@@ -0,0 +1,254 @@
+use crate::csv_format::{csv_reader, parse_cell, CsvFormat};
+use crate::{EmuError, FdaTable};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Number of sectors in which an irregular cutout is divided around the central axis.
+pub const CUTOUT_SECTORS: usize = 360;
+
+/// Custom cutout (insert) for which the correction factor is derived from the FDA table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Cutout {
+    /// Rectangular cutout [cm x cm].
+    Rectangle { width: f64, length: f64 },
+    /// Irregular cutout, vertices (x, y) [cm] of the outline at isocentre relative to the
+    /// central axis.
+    Polygon { points: Vec<(f64, f64)> },
+}
+
+impl std::fmt::Display for Cutout {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Cutout::Rectangle { width, length } => write!(f, "{}x{}", width, length),
+            Cutout::Polygon { points } => write!(f, "polygon ({} vertices)", points.len()),
+        }
+    }
+}
+
+/// Parse the dimensions [cm x cm] encoded in an FDA name such as 3x10 or 2.5x10.
+/// Names with additional text, e.g. 6x10 (AS=2cm), don't describe a plain rectangle
+/// and result in None.
+pub fn parse_fda_dimensions(name: &str) -> Option<(f64, f64)> {
+    let name = name.trim().to_ascii_lowercase();
+    let mut it = name.split('x');
+    let width = it.next()?.trim().parse::<f64>().ok()?;
+    let length = it.next()?.trim().parse::<f64>().ok()?;
+    if it.next().is_some() || !(width > 0.0 && length > 0.0) || !(width + length).is_finite() {
+        return None;
+    }
+    Some((width, length))
+}
+
+impl Cutout {
+    /// Rectangular cutout from its dimensions, e.g. 4.5x7.
+    pub fn parse_rectangle(s: &str) -> Option<Self> {
+        parse_fda_dimensions(s).map(|(width, length)| Cutout::Rectangle { width, length })
+    }
+}
+
+// Cross product of two 2D vectors.
+fn cross(a: (f64, f64), b: (f64, f64)) -> f64 {
+    a.0 * b.1 - a.1 * b.0
+}
+
+/// Distances [cm] from the central axis at which a ray in the direction of the angle
+/// [radians] crosses the outline of the polygon, sorted in ascending order.
+pub fn polygon_crossings(points: &[(f64, f64)], angle: f64) -> Vec<f64> {
+    let d = (angle.cos(), angle.sin());
+    let n = points.len();
+    // side of the ray on which a vertex lies, 0.0 if it lies on the ray
+    let side = |p: (f64, f64)| {
+        let c = cross(d, p);
+        if c.abs() <= 1e-12 * (1.0 + p.0.abs() + p.1.abs()) {
+            0.0
+        } else {
+            c.signum()
+        }
+    };
+    let mut v = vec![];
+    for i in 0..n {
+        let p = points[i];
+        let q = points[(i + 1) % n];
+        let (sp, sq) = (side(p), side(q));
+        if sp * sq < 0.0 {
+            let e = (q.0 - p.0, q.1 - p.1);
+            let t = cross(p, e) / cross(d, e);
+            if t > 0.0 {
+                v.push(t);
+            }
+        } else if sp == 0.0 {
+            // the ray passes through the vertex, it only crosses the outline if the
+            // neighbouring vertices lie on opposite sides
+            let t = p.0 * d.0 + p.1 * d.1;
+            if t > 0.0 && side(points[(i + n - 1) % n]) * sq < 0.0 {
+                v.push(t);
+            }
+        }
+    }
+    v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    v
+}
+
+/// Check that the polygon can be used as a cutout: at least 3 finite vertices.
+pub fn validate_polygon(points: &[(f64, f64)]) -> Result<(), EmuError> {
+    if points.len() < 3 {
+        return Err(EmuError::Cutout(format!(
+            "A polygon needs at least 3 vertices, found [{}]",
+            points.len()
+        )));
+    }
+    if points.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+        return Err(EmuError::Cutout(
+            "The vertices of the polygon must be finite numbers".to_owned(),
+        ));
+    }
+    Ok(())
+}
+
+/// Read a cutout from a JSON file (e.g. {"type": "polygon", "points": [[-2, -3], [2, -3], [0, 4]]})
+/// or the outline of a polygon from a CSV file with the x and y coordinates [cm] of a vertex
+/// on each row.
+pub fn read_cutout(path_buf: PathBuf) -> Result<Cutout, EmuError> {
+    let is_json = matches!(
+        path_buf.extension().and_then(|e| e.to_str()),
+        Some(e) if e.eq_ignore_ascii_case("json")
+    );
+    let cutout = if is_json {
+        let content = std::fs::read_to_string(&path_buf)
+            .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+        serde_json::from_str(&content)
+            .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?
+    } else {
+        // a polygon file has no header rows, the format is detected from the 2 columns
+        let content = std::fs::read_to_string(&path_buf)
+            .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+        let format = CsvFormat::detect_columns(&content, 2);
+        let (mut rdr, format) = csv_reader(&path_buf, Some(format))?;
+        let mut points = vec![];
+        for result in rdr.records() {
+            let record = result.map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+            let x = parse_cell(&path_buf, &record, 0, "a number (x [cm])", &format)?;
+            let y = parse_cell(&path_buf, &record, 1, "a number (y [cm])", &format)?;
+            points.push((x, y));
+        }
+        Cutout::Polygon { points }
+    };
+    if let Cutout::Polygon { points } = &cutout {
+        validate_polygon(points)
+            .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+    }
+    Ok(cutout)
+}
+
+/// Look up the id of an FDA by name.
+/// A name that isn't in the table is an error, even if it describes a rectangle: a cutout
+/// is only derived from the table if the calculation parameters ask for it explicitly.
+pub fn resolve_fda(fda: &FdaTable, name: &str) -> Result<usize, EmuError> {
+    fda.get_id(name)
+        .ok_or_else(|| EmuError::FdaNameNotFound(name.to_owned()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cutout_polygon_crossings() {
+        let square = vec![(-2.0, -2.0), (2.0, -2.0), (2.0, 2.0), (-2.0, 2.0)];
+        assert_eq!(polygon_crossings(&square, 0.0), vec![2.0]);
+        // through a vertex
+        let diagonal = polygon_crossings(&square, std::f64::consts::FRAC_PI_4);
+        assert_eq!(diagonal.len(), 1);
+        assert!((diagonal[0] - 8.0_f64.sqrt()).abs() < 1e-9);
+        // U-shape, the ray along the positive y-axis leaves, re-enters and leaves the cutout
+        let u = vec![
+            (-3.0, -3.0),
+            (3.0, -3.0),
+            (3.0, 3.0),
+            (1.0, 3.0),
+            (1.0, 1.0),
+            (-1.0, 1.0),
+            (-1.0, 3.0),
+            (-3.0, 3.0),
+        ];
+        assert_eq!(
+            polygon_crossings(&u, std::f64::consts::FRAC_PI_2).len(),
+            1,
+            "the ray along the y-axis leaves the cutout at the notch"
+        );
+        let crossings = polygon_crossings(&u, std::f64::consts::FRAC_PI_2 - 0.6);
+        assert_eq!(crossings.len(), 3);
+        assert!(validate_polygon(&square[..2]).is_err());
+    }
+
+    #[test]
+    fn cutout_read_polygon() {
+        let tmp = std::env::temp_dir().join(format!(
+            "emu_check_cutout_read_polygon_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&tmp).unwrap();
+        let points = vec![(-2.0, -3.0), (2.0, -3.0), (0.0, 4.5)];
+        std::fs::write(tmp.join("cutout.csv"), "-2;-3\n2;-3\n0;4,5\n").unwrap();
+        std::fs::write(
+            tmp.join("cutout.json"),
+            r#"{"type": "polygon", "points": [[-2, -3], [2, -3], [0, 4.5]]}"#,
+        )
+        .unwrap();
+        for file_name in &["cutout.csv", "cutout.json"] {
+            assert_eq!(
+                read_cutout(tmp.join(file_name)).unwrap(),
+                Cutout::Polygon {
+                    points: points.clone()
+                }
+            );
+        }
+        // decimal commas in both coordinates
+        std::fs::write(
+            tmp.join("decimal_comma.csv"),
+            "-2,5;-3,5\n2,5;-3,5\n0;4,5\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_cutout(tmp.join("decimal_comma.csv")).unwrap(),
+            Cutout::Polygon {
+                points: vec![(-2.5, -3.5), (2.5, -3.5), (0.0, 4.5)]
+            }
+        );
+        std::fs::write(
+            tmp.join("decimal_point.csv"),
+            "-2.5,-3.5\n2.5,-3.5\n0,4.5\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_cutout(tmp.join("decimal_point.csv")).unwrap(),
+            Cutout::Polygon {
+                points: vec![(-2.5, -3.5), (2.5, -3.5), (0.0, 4.5)]
+            }
+        );
+        std::fs::write(tmp.join("line.csv"), "0,0\n1,1\n").unwrap();
+        assert!(read_cutout(tmp.join("line.csv")).is_err());
+        std::fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn cutout_parse_fda_dimensions() {
+        assert_eq!(parse_fda_dimensions("3x10"), Some((3.0, 10.0)));
+        assert_eq!(parse_fda_dimensions(" 2.5X10 "), Some((2.5, 10.0)));
+        assert_eq!(parse_fda_dimensions("6x10 (AS=2cm)"), None);
+        assert_eq!(parse_fda_dimensions("0x10"), None);
+        assert_eq!(parse_fda_dimensions("2x3x4"), None);
+        assert_eq!(
+            Cutout::parse_rectangle("4.5x7"),
+            Some(Cutout::Rectangle {
+                width: 4.5,
+                length: 7.0
+            })
+        );
+        assert_eq!(
+            Cutout::parse_rectangle("4.5x7").unwrap().to_string(),
+            "4.5x7"
+        );
+    }
+}
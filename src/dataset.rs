@@ -0,0 +1,311 @@
+use crate::uncertainty::check_uncertainty_tables;
+use crate::{
+    load_data, write_calc_options, write_calibration_table, write_energy_table, write_fda_table,
+    write_of_table, CalibrationTable, CorrectionData, CorrectionDataSet, CsvFormat, EmuError,
+    Obliquity, Pdd, Shield, TableKind, CALC_OPTIONS_FILE_NAME, CALIBRATION_FILE_NAME,
+    UNC_FDA_PREFIX, UNC_OF_PREFIX,
+};
+use std::path::{Path, PathBuf};
+
+/// File format of a single-file dataset, determined by the file extension.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DatasetFormat {
+    Json,
+    Toml,
+}
+
+impl DatasetFormat {
+    pub fn from_path(path: &Path) -> Result<Self, EmuError> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("json") => Ok(DatasetFormat::Json),
+            Some(e) if e.eq_ignore_ascii_case("toml") => Ok(DatasetFormat::Toml),
+            _ => Err(EmuError::Format(format!(
+                "{:#?}: Expected a dataset file with the extension json or toml",
+                path
+            ))),
+        }
+    }
+}
+
+// Check that a table of the correction data has a column with a factor per tabulated value
+// for each energy.
+fn check_energy_table<K: TableKind>(
+    cd: &CorrectionData,
+    name: &str,
+    factor: &str,
+    label: &str,
+) -> Result<(), EmuError> {
+    match K::table(cd) {
+        Some(table) if !table.has_valid_dimensions() => Err(EmuError::Format(format!(
+            "{}: The {} must have a column with one {} for each energy",
+            label, name, factor
+        ))),
+        _ => Ok(()),
+    }
+}
+
+// Check that the dimensions of the tables match, a CSV file can't be read otherwise
+// but a dataset file can be edited freely.
+fn validate_correction_data(cd: &CorrectionData) -> Result<(), EmuError> {
+    let label = format!("[machine: {}, applicator: {}]", cd.machine, cd.applicator);
+    let of = &cd.output_factors;
+    if of.zrefs.len() != of.energies.len()
+        || of.table.len() != of.energies.len()
+        || of.table.iter().any(|col| col.len() != of.ssds.len())
+    {
+        return Err(EmuError::Format(format!(
+            "{}: The output factor table must have a zref and a column with \
+            one output factor per SSD for each energy",
+            label
+        )));
+    }
+    let fda = &cd.fda;
+    if fda.ids.len() != fda.names.len()
+        || fda.table.len() != fda.energies.len()
+        || fda.table.iter().any(|col| col.len() != fda.names.len())
+    {
+        return Err(EmuError::Format(format!(
+            "{}: The field defining aperture table must have a column with \
+            one correction factor per FDA for each energy",
+            label
+        )));
+    }
+    check_uncertainty_tables(cd).map_err(EmuError::Format)?;
+    check_energy_table::<Pdd>(cd, "PDD table", "PDD per depth", &label)?;
+    check_energy_table::<Obliquity>(cd, "obliquity table", "factor per angle", &label)?;
+    check_energy_table::<Shield>(cd, "shield backscatter table", "factor per depth", &label)?;
+    if !cd.validate() {
+        return Err(EmuError::Logic(format!(
+            "{}: Mismatch between the energies in the output factor \
+            table and the field defining aperture table.",
+            label
+        )));
+    }
+    Ok(())
+}
+
+/// Read all the configuration data from a JSON or TOML dataset file.
+pub fn read_dataset(path_buf: PathBuf) -> Result<CorrectionDataSet, EmuError> {
+    let format = DatasetFormat::from_path(&path_buf)?;
+    let content = std::fs::read_to_string(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    let cds: CorrectionDataSet = match format {
+        DatasetFormat::Json => serde_json::from_str(&content).map_err(|e| e.to_string()),
+        DatasetFormat::Toml => toml::from_str(&content).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+    for cd in cds.data() {
+        validate_correction_data(cd)
+            .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+    }
+    if cds.data().is_empty() {
+        return Err(EmuError::IO("No configuration data was loaded.".to_owned()));
+    }
+    Ok(cds)
+}
+
+/// Write all the configuration data to a JSON or TOML dataset file.
+pub fn write_dataset(path_buf: PathBuf, cds: &CorrectionDataSet) -> Result<(), EmuError> {
+    let content = match DatasetFormat::from_path(&path_buf)? {
+        DatasetFormat::Json => serde_json::to_string_pretty(cds).map_err(|e| e.to_string()),
+        DatasetFormat::Toml => toml::to_string(cds).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+    std::fs::write(&path_buf, content).map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))
+}
+
+// Part of a file name from a machine or applicator name.
+fn file_name_part(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// Write the table of a kind of the correction data (if available) to <prefix><name>.
+fn write_table_of_kind<K: TableKind>(
+    dir: &Path,
+    name: &str,
+    cd: &CorrectionData,
+) -> Result<(), EmuError> {
+    match K::table(cd) {
+        Some(table) => write_energy_table(
+            dir.join(format!("{}{}", K::PREFIX, name)),
+            &cd.machine,
+            &cd.applicator,
+            table,
+            &CsvFormat::default(),
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Write the configuration data as output factor and field defining aperture CSV files
+/// (of_<machine>_<applicator>.csv and fda_<machine>_<applicator>.csv) in a directory,
+/// together with the PDD (pdd_...), obliquity (obl_...), shield backscatter (shield_...) and
+/// uncertainty tables (unc_of_... and unc_fda_...) and the calibrations (calibration.csv)
+/// if available, and the calculation options (calc_options.json).
+/// The calculation options apply to the whole directory, so all the configuration data must
+/// have the same options.
+pub fn write_data(dirname: &str, vcd: &[CorrectionData]) -> Result<(), EmuError> {
+    let options = vcd.first().map(|cd| &cd.options);
+    if let Some(cd) = vcd.iter().find(|cd| Some(&cd.options) != options) {
+        return Err(EmuError::Format(format!(
+            "The calculation options of machine [{}] and applicator [{}] differ from the others, \
+            they can't be written to one {}",
+            cd.machine, cd.applicator, CALC_OPTIONS_FILE_NAME
+        )));
+    }
+    let dir = PathBuf::from(dirname);
+    std::fs::create_dir_all(&dir).map_err(|e| EmuError::IO(format!("{:#?}: {}", dir, e)))?;
+    let mut calibrations = CalibrationTable::new();
+    for cd in vcd {
+        // every applicator of a machine has a copy of its calibrations
+        for calibration in &cd.calibrations {
+            if !calibrations.calibrations.contains(calibration) {
+                calibrations.calibrations.push(calibration.clone());
+            }
+        }
+        let name = format!(
+            "{}_{}.csv",
+            file_name_part(&cd.machine),
+            file_name_part(&cd.applicator)
+        );
+        write_of_table(
+            dir.join(format!("of_{}", name)),
+            &cd.machine,
+            &cd.applicator,
+            &cd.output_factors,
+        )?;
+        write_fda_table(
+            dir.join(format!("fda_{}", name)),
+            &cd.machine,
+            &cd.applicator,
+            &cd.fda,
+        )?;
+        write_table_of_kind::<Pdd>(&dir, &name, cd)?;
+        write_table_of_kind::<Obliquity>(&dir, &name, cd)?;
+        write_table_of_kind::<Shield>(&dir, &name, cd)?;
+        if let Some(unc) = &cd.uncertainties.output_factors {
+            write_of_table(
+                dir.join(format!("{}{}", UNC_OF_PREFIX, name)),
+                &cd.machine,
+                &cd.applicator,
+                unc,
+            )?;
+        }
+        if let Some(unc) = &cd.uncertainties.fda {
+            write_fda_table(
+                dir.join(format!("{}{}", UNC_FDA_PREFIX, name)),
+                &cd.machine,
+                &cd.applicator,
+                unc,
+            )?;
+        }
+    }
+    if !calibrations.is_empty() {
+        write_calibration_table(dir.join(CALIBRATION_FILE_NAME), &calibrations)?;
+    }
+    if let Some(options) = options {
+        write_calc_options(dir.join(CALC_OPTIONS_FILE_NAME), options)?;
+    }
+    Ok(())
+}
+
+/// Convert the CSV files in a directory into a JSON or TOML dataset file.
+pub async fn convert_data_to_dataset(dirname: &str, path_buf: PathBuf) -> Result<(), EmuError> {
+    let vcd = load_data(dirname).await?;
+    write_dataset(path_buf, &CorrectionDataSet::from(vcd))
+}
+
+/// Convert a JSON or TOML dataset file into CSV files in a directory.
+pub fn convert_dataset_to_data(path_buf: PathBuf, dirname: &str) -> Result<(), EmuError> {
+    let cds = read_dataset(path_buf)?;
+    write_data(dirname, cds.data())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CalcParam, SsdModel};
+
+    #[test]
+    fn dataset_round_trip() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let tmp = std::env::temp_dir().join(format!(
+            "emu_check_dataset_round_trip_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&tmp).unwrap();
+        let cds = CorrectionDataSet::from(&vcd);
+
+        for file_name in &["dataset.json", "dataset.toml"] {
+            let path_buf = tmp.join(file_name);
+            write_dataset(path_buf.clone(), &cds).unwrap();
+            let file_cds = read_dataset(path_buf.clone()).unwrap();
+            assert_eq!(file_cds.data().len(), vcd.len());
+            // the obliquity and shield corrections are applied to a dataset read from a file
+            let mut cp = CalcParam {
+                machine: "Synergy2".to_string(),
+                applicator: "10x10".to_string(),
+                energy: 6.0,
+                ssd: 95.0,
+                depth_zref: 1.36,
+                dose_zref: 100.0,
+                planned_beam_mu: 100.0,
+                fda_id: 1,
+                incidence_angle: Some(45.0),
+                ..CalcParam::new()
+            };
+            assert_eq!(
+                file_cds.compute(&cp).unwrap().factor("obliquity"),
+                Some(1.029)
+            );
+            cp.incidence_angle = None;
+            cp.shield_depth = Some(1.5);
+            assert_eq!(file_cds.compute(&cp).unwrap().factor("shield"), Some(1.629));
+            // a dataset file can be used wherever a data directory is expected
+            let file_vcd =
+                async_std::task::block_on(load_data(path_buf.to_str().unwrap())).unwrap();
+            assert_eq!(file_vcd.len(), vcd.len());
+        }
+
+        let csv_dir = tmp.join("csv");
+        convert_dataset_to_data(tmp.join("dataset.toml"), csv_dir.to_str().unwrap()).unwrap();
+        assert!(csv_dir.join("of_Synergy2_10x10.csv").is_file());
+        let csv_vcd = async_std::task::block_on(load_data(csv_dir.to_str().unwrap())).unwrap();
+        assert_eq!(csv_vcd.len(), vcd.len());
+        for cd in &vcd {
+            let csv_cd = csv_vcd
+                .iter()
+                .find(|c| c.machine == cd.machine && c.applicator == cd.applicator)
+                .unwrap();
+            assert_eq!(csv_cd.output_factors.ssds, cd.output_factors.ssds);
+            assert_eq!(csv_cd.output_factors.table, cd.output_factors.table);
+            assert_eq!(csv_cd.fda.names, cd.fda.names);
+            assert_eq!(csv_cd.fda.table, cd.fda.table);
+        }
+
+        // the calculation options are kept, but must be the same for all the data
+        let mut vcd = vcd;
+        for cd in vcd.iter_mut() {
+            cd.options.energy_interpolation = true;
+            cd.options.ssd_model = SsdModel::VirtualSource;
+        }
+        let options_dir = tmp.join("options");
+        write_data(options_dir.to_str().unwrap(), &vcd).unwrap();
+        let options_vcd =
+            async_std::task::block_on(load_data(options_dir.to_str().unwrap())).unwrap();
+        assert!(options_vcd.iter().all(|cd| cd.options == vcd[0].options));
+        vcd[1].options.energy_interpolation = false;
+        assert!(write_data(options_dir.to_str().unwrap(), &vcd).is_err());
+        std::fs::remove_dir_all(&tmp).unwrap();
+
+        assert!(DatasetFormat::from_path(Path::new("dataset.csv")).is_err());
+    }
+}
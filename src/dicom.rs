@@ -0,0 +1,323 @@
+use crate::errors::EmuError;
+
+// Minimal reader for DICOM part 10 files. Only the little endian transfer syntaxes
+// (implicit and explicit VR) are supported, which covers the RT Plan objects exported
+// by treatment planning systems. Element values are kept as raw bytes and only decoded
+// on request.
+
+const TS_IMPLICIT_VR_LE: &str = "1.2.840.10008.1.2";
+const TS_EXPLICIT_VR_LE: &str = "1.2.840.10008.1.2.1";
+const UNDEFINED_LENGTH: u32 = 0xFFFF_FFFF;
+const ITEM: (u16, u16) = (0xFFFE, 0xE000);
+const ITEM_DELIMITATION: (u16, u16) = (0xFFFE, 0xE00D);
+const SEQUENCE_DELIMITATION: (u16, u16) = (0xFFFE, 0xE0DD);
+
+pub(crate) type Tag = (u16, u16);
+
+#[derive(Debug, Clone)]
+pub(crate) enum Value {
+    Bytes(Vec<u8>),
+    Sequence(Vec<DataSet>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Element {
+    pub tag: Tag,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DataSet {
+    pub elements: Vec<Element>,
+}
+
+impl DataSet {
+    pub fn get(&self, tag: Tag) -> Option<&Element> {
+        self.elements.iter().find(|e| e.tag == tag)
+    }
+
+    // Get the value of a string element with the padding removed.
+    pub fn get_str(&self, tag: Tag) -> Option<String> {
+        match &self.get(tag)?.value {
+            Value::Bytes(b) => Some(
+                String::from_utf8_lossy(b)
+                    .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                    .to_string(),
+            ),
+            Value::Sequence(_) => None,
+        }
+    }
+
+    // Get the first value of a decimal (DS) or integer (IS) string element.
+    pub fn get_f64(&self, tag: Tag) -> Option<f64> {
+        let s = self.get_str(tag)?;
+        s.split('\\').next()?.trim().parse::<f64>().ok()
+    }
+
+    pub fn get_items(&self, tag: Tag) -> &[DataSet] {
+        match self.get(tag).map(|e| &e.value) {
+            Some(Value::Sequence(items)) => items,
+            _ => &[],
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    explicit_vr: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], EmuError> {
+        if self.bytes.len() - self.pos < n {
+            return Err(EmuError::DicomFormat(format!(
+                "Unexpected end of data at byte offset {}",
+                self.pos
+            )));
+        }
+        let s = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    fn read_u16(&mut self) -> Result<u16, EmuError> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, EmuError> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn read_tag(&mut self) -> Result<Tag, EmuError> {
+        Ok((self.read_u16()?, self.read_u16()?))
+    }
+
+    // Read the VR (explicit VR only) and the value length of an element.
+    fn read_vr_length(&mut self, tag: Tag) -> Result<(Option<[u8; 2]>, u32), EmuError> {
+        if !self.explicit_vr || tag.0 == 0xFFFE {
+            return Ok((None, self.read_u32()?));
+        }
+        let b = self.take(2)?;
+        let vr = [b[0], b[1]];
+        match &vr {
+            b"OB" | b"OD" | b"OF" | b"OL" | b"OV" | b"OW" | b"SQ" | b"SV" | b"UC" | b"UN"
+            | b"UR" | b"UT" | b"UV" => {
+                self.take(2)?;
+                Ok((Some(vr), self.read_u32()?))
+            }
+            _ => Ok((Some(vr), self.read_u16()? as u32)),
+        }
+    }
+
+    // Read elements until the end of the data or until the delimiter is found.
+    fn read_data_set(&mut self, delimiter: Option<Tag>) -> Result<DataSet, EmuError> {
+        let mut ds = DataSet::default();
+        while !self.is_empty() {
+            let tag = self.read_tag()?;
+            if Some(tag) == delimiter {
+                self.read_u32()?;
+                break;
+            }
+            let (vr, len) = self.read_vr_length(tag)?;
+            let is_sequence = vr == Some(*b"SQ")
+                || len == UNDEFINED_LENGTH
+                || (vr.is_none() && self.starts_with_item(len));
+            let value = if is_sequence {
+                Value::Sequence(self.read_sequence(len)?)
+            } else {
+                Value::Bytes(self.take(len as usize)?.to_vec())
+            };
+            ds.elements.push(Element { tag, value });
+        }
+        Ok(ds)
+    }
+
+    // Implicit VR data doesn't encode the VR, a value starting with an item tag
+    // is interpreted as a sequence.
+    fn starts_with_item(&self, len: u32) -> bool {
+        if len < 8 || self.bytes.len() - self.pos < 4 {
+            return false;
+        }
+        let b = &self.bytes[self.pos..self.pos + 4];
+        (
+            u16::from_le_bytes([b[0], b[1]]),
+            u16::from_le_bytes([b[2], b[3]]),
+        ) == ITEM
+    }
+
+    fn read_sequence(&mut self, len: u32) -> Result<Vec<DataSet>, EmuError> {
+        let mut items = vec![];
+        if len == UNDEFINED_LENGTH {
+            loop {
+                let tag = self.read_tag()?;
+                let item_len = self.read_u32()?;
+                if tag == SEQUENCE_DELIMITATION {
+                    break;
+                }
+                items.push(self.read_item(tag, item_len)?);
+            }
+        } else {
+            let end = self.pos + len as usize;
+            while self.pos < end {
+                let tag = self.read_tag()?;
+                let item_len = self.read_u32()?;
+                items.push(self.read_item(tag, item_len)?);
+            }
+        }
+        Ok(items)
+    }
+
+    fn read_item(&mut self, tag: Tag, len: u32) -> Result<DataSet, EmuError> {
+        if tag != ITEM {
+            return Err(EmuError::DicomFormat(format!(
+                "Expected a sequence item but found tag ({:04X},{:04X})",
+                tag.0, tag.1
+            )));
+        }
+        if len == UNDEFINED_LENGTH {
+            return self.read_data_set(Some(ITEM_DELIMITATION));
+        }
+        let bytes = self.take(len as usize)?;
+        let mut sub = Reader {
+            bytes,
+            pos: 0,
+            explicit_vr: self.explicit_vr,
+        };
+        sub.read_data_set(None)
+    }
+}
+
+/// Parse the content of a DICOM part 10 file (preamble, file meta information and data set).
+pub(crate) fn parse_dicom(bytes: &[u8]) -> Result<DataSet, EmuError> {
+    if bytes.len() < 132 || &bytes[128..132] != b"DICM" {
+        return Err(EmuError::DicomFormat(
+            "Missing DICOM preamble and 'DICM' prefix".to_owned(),
+        ));
+    }
+    // The file meta information is always encoded as explicit VR little endian.
+    let mut rdr = Reader {
+        bytes,
+        pos: 132,
+        explicit_vr: true,
+    };
+    let mut meta = DataSet::default();
+    while rdr.bytes[rdr.pos..].starts_with(&[0x02, 0x00]) {
+        let tag = rdr.read_tag()?;
+        let (_, len) = rdr.read_vr_length(tag)?;
+        let value = Value::Bytes(rdr.take(len as usize)?.to_vec());
+        meta.elements.push(Element { tag, value });
+    }
+    let ts = meta.get_str((0x0002, 0x0010)).unwrap_or_default();
+    rdr.explicit_vr = match ts.as_str() {
+        TS_IMPLICIT_VR_LE => false,
+        TS_EXPLICIT_VR_LE => true,
+        _ => {
+            return Err(EmuError::DicomFormat(format!(
+                "Unsupported transfer syntax [{}]",
+                ts
+            )))
+        }
+    };
+    rdr.read_data_set(None)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    // Helpers to encode a DICOM file in memory for the unit tests.
+    pub(crate) fn explicit_element(tag: Tag, vr: &[u8; 2], value: &[u8]) -> Vec<u8> {
+        let mut v = vec![];
+        v.extend_from_slice(&tag.0.to_le_bytes());
+        v.extend_from_slice(&tag.1.to_le_bytes());
+        v.extend_from_slice(vr);
+        let mut value = value.to_vec();
+        if value.len() % 2 == 1 {
+            value.push(if vr == b"UI" { 0 } else { b' ' });
+        }
+        if vr == b"SQ" {
+            v.extend_from_slice(&[0, 0]);
+            v.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        } else {
+            v.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        }
+        v.extend_from_slice(&value);
+        v
+    }
+
+    pub(crate) fn explicit_str(tag: Tag, vr: &[u8; 2], value: &str) -> Vec<u8> {
+        explicit_element(tag, vr, value.as_bytes())
+    }
+
+    pub(crate) fn item(content: &[u8]) -> Vec<u8> {
+        let mut v = vec![];
+        v.extend_from_slice(&ITEM.0.to_le_bytes());
+        v.extend_from_slice(&ITEM.1.to_le_bytes());
+        v.extend_from_slice(&(content.len() as u32).to_le_bytes());
+        v.extend_from_slice(content);
+        v
+    }
+
+    pub(crate) fn part10(ts: &str, data_set: &[u8]) -> Vec<u8> {
+        let mut v = vec![0u8; 128];
+        v.extend_from_slice(b"DICM");
+        v.extend_from_slice(&explicit_str((0x0002, 0x0010), b"UI", ts));
+        v.extend_from_slice(data_set);
+        v
+    }
+
+    fn implicit_header(tag: Tag, len: u32) -> Vec<u8> {
+        let mut v = vec![];
+        v.extend_from_slice(&tag.0.to_le_bytes());
+        v.extend_from_slice(&tag.1.to_le_bytes());
+        v.extend_from_slice(&len.to_le_bytes());
+        v
+    }
+
+    #[test]
+    fn parse_explicit_vr() {
+        let mut ds = explicit_str((0x0008, 0x0060), b"CS", "RTPLAN");
+        let inner = explicit_str((0x300A, 0x0114), b"DS", "12");
+        ds.extend(explicit_element((0x300A, 0x0111), b"SQ", &item(&inner)));
+        let bytes = part10(TS_EXPLICIT_VR_LE, &ds);
+        let ds = parse_dicom(&bytes).unwrap();
+        assert_eq!(ds.get_str((0x0008, 0x0060)).unwrap(), "RTPLAN");
+        let items = ds.get_items((0x300A, 0x0111));
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].get_f64((0x300A, 0x0114)).unwrap(), 12.0);
+    }
+
+    #[test]
+    fn parse_implicit_vr_undefined_length() {
+        let mut ds = implicit_header((0x0008, 0x0060), 6);
+        ds.extend_from_slice(b"RTPLAN");
+        ds.extend(implicit_header((0x300A, 0x00B0), UNDEFINED_LENGTH));
+        ds.extend(implicit_header(ITEM, UNDEFINED_LENGTH));
+        ds.extend(implicit_header((0x300A, 0x00B2), 8));
+        ds.extend_from_slice(b"Synergy2");
+        ds.extend(implicit_header(ITEM_DELIMITATION, 0));
+        ds.extend(implicit_header(SEQUENCE_DELIMITATION, 0));
+        ds.extend(implicit_header((0x300A, 0x00C0), 2));
+        ds.extend_from_slice(b"1 ");
+        let bytes = part10(TS_IMPLICIT_VR_LE, &ds);
+        let ds = parse_dicom(&bytes).unwrap();
+        let items = ds.get_items((0x300A, 0x00B0));
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].get_str((0x300A, 0x00B2)).unwrap(), "Synergy2");
+        assert_eq!(ds.get_f64((0x300A, 0x00C0)).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(parse_dicom(b"not a dicom file").is_err());
+        let bytes = part10("1.2.840.10008.1.2.2", &[]);
+        assert!(parse_dicom(&bytes).is_err());
+    }
+}
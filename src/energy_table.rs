@@ -0,0 +1,295 @@
+use crate::correction_data::find_correction_data;
+use crate::csv_format::{
+    check_energies, csv_reader, expect_label, format_number, label_row, parse_cell, parse_cells,
+    record_row, write_rows, CsvFormat,
+};
+use crate::errors::EmuError;
+use crate::ipol::{check_abscissae, energy_column, Interpolator, LinearInterpolator};
+use crate::CorrectionData;
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+/// Kind of factors in an EnergyTable (e.g. PDDs as a function of the depth).
+pub trait TableKind: std::fmt::Debug + Clone {
+    /// Prefix of the CSV files with the table.
+    const PREFIX: &'static str;
+    /// Label of the column with the tabulated variable, e.g. Depth.
+    const LABEL: &'static str;
+    /// Tabulated variable and its unit in error messages, e.g. depth [cm].
+    const VARIABLE: &'static str;
+    /// Tabulated factor in error messages, e.g. PDD [%].
+    const FACTOR: &'static str;
+
+    /// Error for a value of the variable outside the tabulated range.
+    fn out_of_range(x: f64) -> EmuError;
+
+    /// Table of this kind in the correction data.
+    fn table(cd: &CorrectionData) -> Option<&EnergyTable<Self>>;
+
+    /// Add a table of this kind to the correction data.
+    fn set_table(cd: &mut CorrectionData, table: EnergyTable<Self>);
+}
+
+/// Factors per energy as a function of one variable (depth or angle), see TableKind.
+/// The CSV file has the machine and applicator on the first two rows, the label of the
+/// variable and the energies on the third row and the factors of one value of the variable
+/// on each following row.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound = "")]
+pub struct EnergyTable<K> {
+    pub(crate) energies: Vec<f64>,
+    pub(crate) abscissae: Vec<f64>, // tabulated values of the variable
+    // table.get(i) gets the i th column in the table
+    // [each column contains the factors for one energy].
+    pub(crate) table: Vec<Vec<f64>>,
+    #[serde(skip)]
+    kind: PhantomData<K>,
+}
+
+impl<K: TableKind> EnergyTable<K> {
+    pub fn new() -> Self {
+        Self {
+            energies: vec![],
+            abscissae: vec![],
+            table: vec![],
+            kind: PhantomData,
+        }
+    }
+
+    pub fn set_energies(&mut self, values: Vec<f64>) {
+        self.energies = values;
+    }
+
+    pub fn get_energies(&self) -> &Vec<f64> {
+        &self.energies
+    }
+
+    /// Tabulated values of the variable.
+    pub fn get_abscissae(&self) -> &Vec<f64> {
+        &self.abscissae
+    }
+
+    // Add a list of factors (one per energy) for a given value of the variable.
+    pub fn add_factors(&mut self, x: f64, factors: Vec<f64>) -> Result<(), EmuError> {
+        if factors.len() != self.energies.len() {
+            return Err(EmuError::Str(format!(
+                "Mismatch between the number energies [{}] and the number of factors [{}] ({})",
+                self.energies.len(),
+                factors.len(),
+                K::FACTOR
+            )));
+        }
+        if self.table.is_empty() {
+            self.table = vec![vec![]; self.energies.len()];
+        }
+        self.abscissae.push(x);
+        for (col, factor) in self.table.iter_mut().zip(factors) {
+            col.push(factor);
+        }
+        Ok(())
+    }
+
+    /// True if the table has a column with one factor per tabulated value for each energy.
+    pub fn has_valid_dimensions(&self) -> bool {
+        self.table.len() == self.energies.len()
+            && self
+                .table
+                .iter()
+                .all(|col| col.len() == self.abscissae.len())
+    }
+
+    /// Get the factor, interpolated linearly between the tabulated values of the variable.
+    pub fn get_cf(&self, energy: f64, x: f64, energy_interpolation: bool) -> Result<f64, EmuError> {
+        check_abscissae(K::VARIABLE, &self.abscissae)?;
+        let (factors, _) =
+            energy_column(&self.energies, &self.table, energy, energy_interpolation)?;
+        LinearInterpolator
+            .interpolate(&self.abscissae, &factors, x)
+            .ok_or_else(|| K::out_of_range(x))
+    }
+}
+
+impl<K: TableKind> Default for EnergyTable<K> {
+    fn default() -> Self {
+        EnergyTable::new()
+    }
+}
+
+/// Read the table using the given CSV format, or the format detected from the content if None.
+pub fn read_energy_table<K: TableKind>(
+    path_buf: PathBuf,
+    opt_format: Option<CsvFormat>,
+) -> Result<(String, String, EnergyTable<K>), EmuError> {
+    let mut energy_table = EnergyTable::new();
+    let mut machine = "".to_owned();
+    let mut applicator = "".to_owned();
+    let (mut rdr, format) = csv_reader(&path_buf, opt_format)?;
+    let path = path_buf.as_path();
+    let expected_x = format!("a number ({})", K::VARIABLE);
+    let expected_factor = format!("a number ({})", K::FACTOR);
+    let mut nc = 0;
+    let mut i = 0;
+    for record in rdr.records() {
+        let record = record.map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+        let nrecord = record.len();
+        if nrecord == 0 {
+            continue;
+        }
+        if nc == 0 {
+            nc = nrecord;
+        }
+        if nc != nrecord {
+            return Err(EmuError::Format(format!(
+                "{:#?} row {}: All rows in the CSV file must have the same number of columns [{} <-> {}]",
+                path_buf,
+                record_row(&record),
+                nc,
+                nrecord
+            )));
+        }
+        if i == 0 {
+            machine = record[0].to_string();
+        } else if i == 1 {
+            expect_label(path, &record, 0, "Applicator")?;
+            applicator = record[1].to_string();
+        } else if i == 2 {
+            expect_label(path, &record, 0, K::LABEL)?;
+            energy_table.energies =
+                parse_cells(path, &record, 1, "a number (energy [MeV])", &format)?;
+        } else {
+            let x = parse_cell(path, &record, 0, &expected_x, &format)?;
+            let factors = parse_cells(path, &record, 1, &expected_factor, &format)?;
+            energy_table.add_factors(x, factors)?;
+        }
+        i += 1;
+    }
+    Ok((machine, applicator, energy_table))
+}
+
+pub fn write_energy_table<K: TableKind>(
+    path_buf: PathBuf,
+    machine: &str,
+    applicator: &str,
+    energy_table: &EnergyTable<K>,
+    format: &CsvFormat,
+) -> Result<(), EmuError> {
+    check_energies(&path_buf, &energy_table.energies)?;
+    let nc = energy_table.energies.len() + 1;
+    let mut row = vec![K::LABEL.to_owned()];
+    row.extend(
+        energy_table
+            .energies
+            .iter()
+            .map(|x| format_number(*x, format)),
+    );
+    let mut rows = vec![
+        label_row(machine, "", nc),
+        label_row("Applicator", applicator, nc),
+        row,
+    ];
+    for (i, x) in energy_table.abscissae.iter().enumerate() {
+        let mut row = vec![format_number(*x, format)];
+        row.extend(
+            energy_table
+                .table
+                .iter()
+                .map(|col| format_number(col[i], format)),
+        );
+        rows.push(row);
+    }
+    write_rows(&path_buf, &rows, format)
+}
+
+/// Read the tables of a kind (files starting with its prefix) in a directory and add them
+/// to the matching correction data.
+pub fn load_energy_tables<K: TableKind>(
+    dirname: &str,
+    opt_format: Option<CsvFormat>,
+    vcd: &mut [CorrectionData],
+) -> Result<(), EmuError> {
+    for entry in std::fs::read_dir(PathBuf::from(dirname))? {
+        let ep = entry?.path();
+        let file_name = ep.file_name().and_then(|s| s.to_str()).unwrap_or("");
+        if !file_name.starts_with(K::PREFIX) {
+            continue;
+        }
+        let (machine, applicator, table) = read_energy_table::<K>(ep.clone(), opt_format)?;
+        let cd = find_correction_data(vcd, &machine, &applicator, &ep)?;
+        K::set_table(cd, table);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Obliquity, Pdd, Shield};
+
+    fn round_trip<K: TableKind>(file_name: &str) -> EnergyTable<K> {
+        let (machine, applicator, energy_table) =
+            read_energy_table::<K>(PathBuf::from("resources").join(file_name), None).unwrap();
+        assert_eq!(machine, "Synergy2");
+        assert_eq!(applicator, "10x10");
+        assert!(energy_table.has_valid_dimensions());
+
+        let formats = [
+            CsvFormat::default(),
+            CsvFormat {
+                delimiter: ';',
+                decimal_separator: ',',
+            },
+        ];
+        let dir = std::env::temp_dir().join(format!(
+            "emu_check_round_trip_{}_{}",
+            file_name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, format) in formats.iter().enumerate() {
+            let tmp = dir.join(format!("{}_{}", i, file_name));
+            write_energy_table(tmp.clone(), &machine, &applicator, &energy_table, format).unwrap();
+            let (_, _, read) = read_energy_table::<K>(tmp.clone(), None).unwrap();
+            std::fs::remove_file(&tmp).unwrap();
+            assert_eq!(read.energies, energy_table.energies);
+            assert_eq!(read.abscissae, energy_table.abscissae);
+            assert_eq!(read.table, energy_table.table);
+        }
+        let tmp = dir.join("empty.csv");
+        let empty = EnergyTable::<K>::new();
+        assert!(write_energy_table(tmp, &machine, &applicator, &empty, &formats[0]).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        energy_table
+    }
+
+    #[test]
+    fn energy_table_round_trip() {
+        let pdd = round_trip::<Pdd>("pdd_app_10x10.csv");
+        assert_eq!(pdd.get_cf(6.0, 1.5, false).unwrap(), 100.0);
+        let expected =
+            (pdd.get_cf(12.0, 2.0, false).unwrap() + pdd.get_cf(12.0, 2.5, false).unwrap()) / 2.0;
+        assert!((pdd.get_cf(12.0, 2.25, false).unwrap() - expected).abs() < 1e-12);
+        assert!(matches!(
+            pdd.get_cf(12.0, 20.0, false),
+            Err(EmuError::DepthNotFound(_))
+        ));
+
+        let obliquity = round_trip::<Obliquity>("obl_app_10x10.csv");
+        assert_eq!(obliquity.get_cf(6.0, 0.0, false).unwrap(), 1.0);
+        let cf = obliquity.get_cf(12.0, 35.0, false).unwrap();
+        assert!((cf - (1.005 + 1.012) / 2.0).abs() < 1e-12);
+        assert!(matches!(
+            obliquity.get_cf(12.0, 75.0, false),
+            Err(EmuError::AngleNotFound(_))
+        ));
+
+        let shield = round_trip::<Shield>("shield_app_10x10.csv");
+        assert_eq!(shield.get_cf(6.0, 1.0, false).unwrap(), 1.597);
+        let cf = shield.get_cf(12.0, 0.75, false).unwrap();
+        assert!((cf - (1.415 + 1.437) / 2.0).abs() < 1e-12);
+        assert!(matches!(
+            shield.get_cf(12.0, 3.0, false),
+            Err(EmuError::ShieldDepthNotFound(_))
+        ));
+    }
+}
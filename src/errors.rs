@@ -1,3 +1,4 @@
+use chrono::NaiveDate;
 use serde::{Deserialize, Serialize};
 use std::io::Error;
 use std::path::PathBuf;
@@ -17,6 +18,29 @@ pub enum EmuError {
     Format(String),
     DirNotFound(PathBuf),
     IO(String),
+    DicomFormat(String),
+    RtPlan(String),
+    RtPlanBeam(String, Box<EmuError>),
+    FdaNameNotFound(String),
+    MissingInput(String),
+    InvalidInput(String, String),
+    Parse {
+        path: PathBuf,
+        row: usize,    // 1-based
+        column: usize, // 1-based
+        cell: String,
+        expected: String,
+    },
+    ManifestMismatch(String, Vec<String>),
+    ManifestNotFound(PathBuf),
+    Cutout(String),
+    DepthNotFound(f64),
+    PddTableNotFound(String, String),
+    CalibrationNotFound(String, f64, NaiveDate),
+    AngleNotFound(f64),
+    ObliquityTableNotFound(String, String),
+    ShieldDepthNotFound(f64),
+    ShieldTableNotFound(String, String),
 }
 
 impl std::fmt::Display for EmuError {
@@ -43,6 +67,78 @@ impl std::fmt::Display for EmuError {
                 write!(f, "Directory not found or does not exist: {:#?}", path_buf)
             }
             EmuError::IO(msg) => write!(f, "Input / output error: {}", msg),
+            EmuError::DicomFormat(msg) => write!(f, "Invalid DICOM file: {}", msg),
+            EmuError::RtPlan(msg) => write!(f, "RT Plan: {}", msg),
+            EmuError::RtPlanBeam(beam, e) => write!(f, "RT Plan beam [{}]: {}", beam, e),
+            EmuError::FdaNameNotFound(name) => {
+                write!(f, "FDA [{}] not found", name)
+            }
+            EmuError::MissingInput(field) => {
+                write!(f, "Calculation parameter [{}] is missing", field)
+            }
+            EmuError::InvalidInput(field, value) => {
+                write!(
+                    f,
+                    "Invalid value [{}] for calculation parameter [{}]",
+                    value, field
+                )
+            }
+            EmuError::Parse {
+                path,
+                row,
+                column,
+                cell,
+                expected,
+            } => write!(
+                f,
+                "Invalid format: {:#?} row {}, column {}: expected {} but found [{}]",
+                path, row, column, expected, cell
+            ),
+            EmuError::ManifestMismatch(version, mismatches) => write!(
+                f,
+                "The configuration data doesn't match the approved version [{}] in the manifest: {}",
+                version,
+                mismatches.join("; ")
+            ),
+            EmuError::ManifestNotFound(path_buf) => write!(
+                f,
+                "The configuration data has no manifest, it can't be verified: {:#?}",
+                path_buf
+            ),
+            EmuError::Cutout(msg) => write!(f, "Cutout: {}", msg),
+            EmuError::DepthNotFound(depth) => {
+                write!(f, "Depth [{}] is out of range of the PDD table", depth)
+            }
+            EmuError::PddTableNotFound(machine, applicator) => write!(
+                f,
+                "No PDD table found for machine [{}] and applicator [{}]",
+                machine, applicator
+            ),
+            EmuError::CalibrationNotFound(machine, energy, date) => write!(
+                f,
+                "No calibration of machine [{}] and energy [{}] is effective on {}",
+                machine, energy, date
+            ),
+            EmuError::AngleNotFound(angle) => write!(
+                f,
+                "Angle of incidence [{}] is out of range of the obliquity table",
+                angle
+            ),
+            EmuError::ObliquityTableNotFound(machine, applicator) => write!(
+                f,
+                "No obliquity table found for machine [{}] and applicator [{}]",
+                machine, applicator
+            ),
+            EmuError::ShieldDepthNotFound(depth) => write!(
+                f,
+                "Shield depth [{}] is out of range of the backscatter table",
+                depth
+            ),
+            EmuError::ShieldTableNotFound(machine, applicator) => write!(
+                f,
+                "No shield backscatter table found for machine [{}] and applicator [{}]",
+                machine, applicator
+            ),
         }
     }
 }
@@ -1,4 +1,12 @@
+use crate::csv_format::{
+    check_energies, csv_reader, expect_label, format_number, label_row, parse_cell, parse_cells,
+    record_row, write_rows, CsvFormat,
+};
+use crate::cutout::{
+    parse_fda_dimensions, polygon_crossings, validate_polygon, Cutout, CUTOUT_SECTORS,
+};
 use crate::errors::EmuError;
+use crate::ipol::{energy_column, find_neighbours, interpolate_linear};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -11,6 +19,50 @@ pub struct FdaTable {
                                      // in the table [each column contains the output factors for one energy].
 }
 
+/// Correction factor of a field defining aperture or cutout.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FdaFactor {
+    pub cf: f64,
+    pub derived: bool, // true if derived from the tabulated factors instead of measured
+}
+
+// Correction factors of square fields (side, factor) sorted by side. Squares that aren't
+// tabulated are derived from rectangles with the square-root rule
+// CF(a x b) = sqrt(CF(a x a) * CF(b x b)) if the factor of the other side is known.
+fn square_factors(rects: &[(f64, f64, f64)]) -> Vec<(f64, f64)> {
+    let mut squares: Vec<(f64, f64)> = vec![];
+    let known = |squares: &[(f64, f64)], side: f64| {
+        squares
+            .iter()
+            .find(|(s, _)| (s - side).abs() < f64::EPSILON)
+            .map(|(_, cf)| *cf)
+    };
+    for (a, b, cf) in rects {
+        if (a - b).abs() < f64::EPSILON && known(&squares, *a).is_none() {
+            squares.push((*a, *cf));
+        }
+    }
+    loop {
+        let mut added = false;
+        for (a, b, cf) in rects {
+            for (side, other) in &[(*a, *b), (*b, *a)] {
+                if known(&squares, *side).is_some() {
+                    continue;
+                }
+                if let Some(cf_other) = known(&squares, *other) {
+                    squares.push((*side, cf * cf / cf_other));
+                    added = true;
+                }
+            }
+        }
+        if !added {
+            break;
+        }
+    }
+    squares.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+    squares
+}
+
 impl FdaTable {
     pub fn new() -> Self {
         Self {
@@ -59,48 +111,186 @@ impl FdaTable {
         Ok(())
     }
 
-    // Get the correction factor based on the field defining aperture.
-    pub fn get_cf(&self, energy: f64, fda_id: usize) -> Result<f64, EmuError> {
-        let nenergies = self.energies.len();
-        let mut energy_idx = nenergies;
-        for idx in 0..nenergies {
-            if (energy - *self.energies.get(idx).unwrap()).abs() < std::f64::EPSILON {
-                energy_idx = idx;
-                break;
-            }
-        }
-        if nenergies == energy_idx {
-            return Err(EmuError::EnergyNotFound(energy));
-        }
+    // Get the id of a field defining aperture by its name, ignoring case and surrounding whitespace.
+    pub fn get_id(&self, name: &str) -> Option<usize> {
+        let idx = self
+            .names
+            .iter()
+            .position(|n| n.trim().eq_ignore_ascii_case(name.trim()))?;
+        self.ids.get(idx).copied()
+    }
 
-        // Found a matching energy, get the correction factor by fda ID
-        let nids = self.ids.len();
-        let mut fda_idx = nids;
-        for idx in 0..nids {
-            if fda_id == *self.ids.get(idx).unwrap() {
-                fda_idx = idx;
-                break;
+    // Tabulated rectangles (width, length, factor) in the FDA table for an energy.
+    fn rectangles(
+        &self,
+        energy: f64,
+        energy_interpolation: bool,
+    ) -> Result<Vec<(f64, f64, f64)>, EmuError> {
+        let (col, _) = energy_column(&self.energies, &self.table, energy, energy_interpolation)?;
+        Ok(self
+            .names
+            .iter()
+            .zip(col.iter())
+            .filter_map(|(name, cf)| parse_fda_dimensions(name).map(|(a, b)| (a, b, *cf)))
+            .collect())
+    }
+
+    /// Get the correction factor of a cutout.
+    pub fn get_cf_cutout(
+        &self,
+        energy: f64,
+        cutout: &Cutout,
+        energy_interpolation: bool,
+    ) -> Result<FdaFactor, EmuError> {
+        match cutout {
+            Cutout::Rectangle { width, length } => {
+                self.get_cf_rectangle(energy, *width, *length, energy_interpolation)
             }
+            Cutout::Polygon { points } => Ok(FdaFactor {
+                cf: self.get_cf_polygon(energy, points, energy_interpolation)?,
+                derived: true,
+            }),
         }
-        if nids == fda_idx {
-            return Err(EmuError::FdaIDNotFound(fda_id));
+    }
+
+    /// Get the correction factor of a rectangular cutout [cm x cm].
+    /// The measured factor is used if the rectangle is tabulated (FDA names such as 3x10),
+    /// otherwise it's derived with the square-root rule from the square field factors,
+    /// interpolated linearly on the side of the equivalent square.
+    pub fn get_cf_rectangle(
+        &self,
+        energy: f64,
+        width: f64,
+        length: f64,
+        energy_interpolation: bool,
+    ) -> Result<FdaFactor, EmuError> {
+        let rects = self.rectangles(energy, energy_interpolation)?;
+        let same = |x: f64, y: f64| (x - y).abs() < f64::EPSILON;
+        if let Some((_, _, cf)) = rects.iter().find(|(a, b, _)| {
+            (same(*a, width) && same(*b, length)) || (same(*a, length) && same(*b, width))
+        }) {
+            return Ok(FdaFactor {
+                cf: *cf,
+                derived: false,
+            });
         }
+        let squares = square_factors(&rects);
+        let sides: Vec<f64> = squares.iter().map(|(side, _)| *side).collect();
+        let square_cf = |side: f64| -> Result<f64, EmuError> {
+            let (i0, i1) = find_neighbours(&sides, side).ok_or_else(|| {
+                EmuError::Cutout(format!(
+                    "Side [{}] of cutout [{}x{}] is outside the range of the square fields {:?} \
+                    derived from the FDA table",
+                    side, width, length, sides
+                ))
+            })?;
+            Ok(interpolate_linear(
+                side,
+                squares[i0].0,
+                squares[i1].0,
+                squares[i0].1,
+                squares[i1].1,
+            ))
+        };
+        Ok(FdaFactor {
+            cf: (square_cf(width)? * square_cf(length)?).sqrt(),
+            derived: true,
+        })
+    }
 
-        let opt_col = self.table.get(energy_idx);
-        if opt_col.is_none() {
-            return Err(EmuError::Logic(
-                "Energy matching correction factor column in table was not found.".to_string(),
+    /// Get the correction factor of an irregular cutout by sector integration around the
+    /// central axis: CF = 1/N * sum CF_circle(r_i) over N equal sectors, with r_i the radius
+    /// of the cutout in the sector. If a sector crosses the outline more than once, the
+    /// factors of the crossings are added and subtracted alternately.
+    /// The radial factors are the factors of the square fields with the same area
+    /// (side = r * sqrt(pi)). Radii beyond the largest square use the factor of the largest
+    /// square, as the field is then limited by the applicator.
+    pub fn get_cf_polygon(
+        &self,
+        energy: f64,
+        points: &[(f64, f64)],
+        energy_interpolation: bool,
+    ) -> Result<f64, EmuError> {
+        validate_polygon(points)?;
+        let squares = square_factors(&self.rectangles(energy, energy_interpolation)?);
+        if squares.is_empty() {
+            return Err(EmuError::Cutout(
+                "No square fields can be derived from the FDA table".to_owned(),
             ));
         }
-        let col = opt_col.unwrap();
+        let radii: Vec<f64> = squares
+            .iter()
+            .map(|(side, _)| side / std::f64::consts::PI.sqrt())
+            .collect();
+        let circle_cf = |r: f64| -> Result<f64, EmuError> {
+            let n = radii.len();
+            if r >= radii[n - 1] {
+                return Ok(squares[n - 1].1);
+            }
+            let (i0, i1) = find_neighbours(&radii, r).ok_or_else(|| {
+                EmuError::Cutout(format!(
+                    "Radius [{:.2}] of the cutout is smaller than the smallest equivalent \
+                    circle [{:.2}] derived from the FDA table",
+                    r, radii[0]
+                ))
+            })?;
+            Ok(interpolate_linear(
+                r,
+                radii[i0],
+                radii[i1],
+                squares[i0].1,
+                squares[i1].1,
+            ))
+        };
+        let dtheta = 2.0 * std::f64::consts::PI / CUTOUT_SECTORS as f64;
+        let mut sum = 0.0;
+        for i in 0..CUTOUT_SECTORS {
+            let crossings = polygon_crossings(points, (i as f64 + 0.5) * dtheta);
+            if crossings.len() % 2 != 1 {
+                return Err(EmuError::Cutout(
+                    "The central axis must be inside the polygon of the cutout".to_owned(),
+                ));
+            }
+            for (j, r) in crossings.iter().enumerate() {
+                let cf = circle_cf(*r)?;
+                sum += if j % 2 == 0 { cf } else { -cf };
+            }
+        }
+        Ok(sum / CUTOUT_SECTORS as f64)
+    }
+
+    // Get the correction factor based on the field defining aperture.
+    pub fn get_cf(&self, energy: f64, fda_id: usize) -> Result<f64, EmuError> {
+        self.lookup(energy, fda_id, false)
+    }
+
+    // Get the correction factor based on the field defining aperture, interpolated between the
+    // neighbouring tabulated energies if the energy isn't tabulated.
+    pub fn get_cf_ipol_energy(&self, energy: f64, fda_id: usize) -> Result<f64, EmuError> {
+        self.lookup(energy, fda_id, true)
+    }
+
+    fn lookup(
+        &self,
+        energy: f64,
+        fda_id: usize,
+        energy_interpolation: bool,
+    ) -> Result<f64, EmuError> {
+        let (col, _) = energy_column(&self.energies, &self.table, energy, energy_interpolation)?;
+
+        // Found a matching energy, get the correction factor by fda ID
+        let fda_idx = self
+            .ids
+            .iter()
+            .position(|id| *id == fda_id)
+            .ok_or(EmuError::FdaIDNotFound(fda_id))?;
         let opt_cf = col.get(fda_idx);
         if opt_cf.is_none() {
             return Err(EmuError::Logic(
                 "FDA id matching correction factor column in table was not found".to_string(),
             ));
         }
-        let cf = opt_cf.unwrap();
-        Ok(*cf)
+        Ok(*opt_cf.unwrap())
     }
 }
 
@@ -111,21 +301,24 @@ impl Default for FdaTable {
 }
 
 pub fn read_fda_table(path_buf: PathBuf) -> Result<(String, String, FdaTable), EmuError> {
+    read_fda_table_with_format(path_buf, None)
+}
+
+/// Read the table using the given CSV format, or the format detected from the content if None.
+pub fn read_fda_table_with_format(
+    path_buf: PathBuf,
+    opt_format: Option<CsvFormat>,
+) -> Result<(String, String, FdaTable), EmuError> {
     let mut fda_table = FdaTable::new();
     let mut machine = "".to_owned();
     let mut applicator = "".to_owned();
-    let res_rdr = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_path(path_buf.clone());
-    if let Err(e) = res_rdr {
-        return Err(EmuError::IO(format!("{:#?}: {}", path_buf, e.to_string())));
-    }
-    let mut rdr = res_rdr.unwrap();
+    let (mut rdr, format) = csv_reader(&path_buf, opt_format)?;
+    let path = path_buf.as_path();
     let mut nc = 0;
     let mut i = 0;
     for record in rdr.records() {
         if let Err(e) = record {
-            return Err(EmuError::IO(format!("{:#?}: {}", path_buf, e.to_string())));
+            return Err(EmuError::IO(format!("{:#?}: {}", path_buf, e)));
         }
         let record = record.unwrap();
         let nrecord = record.len();
@@ -137,63 +330,79 @@ pub fn read_fda_table(path_buf: PathBuf) -> Result<(String, String, FdaTable), E
         }
         if nc != nrecord {
             return Err(EmuError::Format(format!(
-                "All rows in the CSV file must have the same number of columns [{} <-> {}]",
-                nc, nrecord
+                "{:#?} row {}: All rows in the CSV file must have the same number of columns [{} <-> {}]",
+                path_buf,
+                record_row(&record),
+                nc,
+                nrecord
             )));
         }
         if i == 0 {
             machine = record[0].to_string();
         } else if i == 1 {
-            if &record[0] != "Applicator" {
-                return Err(EmuError::Format(
-                    "Expected the label \'Applicator\' on row 1, column 0".to_owned(),
-                ));
-            }
+            expect_label(path, &record, 0, "Applicator")?;
             applicator = record[1].to_string();
         } else if i == 2 {
-            if &record[0] != "Dimensions" {
-                return Err(EmuError::Format(
-                    "Expected the label \'Dimensions\' on row 2, column 0".to_owned(),
-                ));
-            }
-            if &record[1] != "id" {
-                return Err(EmuError::Format(
-                    "Expected the label \'id\' on row 2, column 1".to_owned(),
-                ));
-            }
-            let mut energies = Vec::with_capacity(nrecord - 1);
-            for j in 2..nrecord {
-                let s = &record[j];
-                let res_f = s.parse::<f64>();
-                if let Err(e) = res_f {
-                    return Err(EmuError::Format(e.to_string()));
-                }
-                energies.push(res_f.unwrap());
-            }
-            fda_table.energies = energies;
+            expect_label(path, &record, 0, "Dimensions")?;
+            expect_label(path, &record, 1, "id")?;
+            fda_table.energies = parse_cells(path, &record, 2, "a number (energy [MeV])", &format)?;
         } else {
             let name = &record[0];
-            let sid = &record[1];
-            let res_id = sid.parse::<usize>();
-            if let Err(e) = res_id {
-                return Err(EmuError::Format(e.to_string()));
-            }
-            let mut v = vec![];
-            for j in 2..nrecord {
-                let s = &record[j];
-                let res_f = s.parse::<f64>();
-                if let Err(e) = res_f {
-                    return Err(EmuError::Format(e.to_string()));
-                }
-                v.push(res_f.unwrap());
-            }
-            fda_table.add(name, res_id.unwrap(), v)?;
+            let id = parse_cell(path, &record, 1, "an integer (FDA id)", &format)?;
+            let v = parse_cells(path, &record, 2, "a number (correction factor)", &format)?;
+            fda_table.add(name, id, v)?;
         }
         i += 1;
     }
     Ok((machine, applicator, fda_table))
 }
 
+/// Write the table, together with its machine and applicator, in the layout read by read_fda_table.
+pub fn write_fda_table(
+    path_buf: PathBuf,
+    machine: &str,
+    applicator: &str,
+    fda_table: &FdaTable,
+) -> Result<(), EmuError> {
+    write_fda_table_with_format(
+        path_buf,
+        machine,
+        applicator,
+        fda_table,
+        &CsvFormat::default(),
+    )
+}
+
+/// Write the table using the delimiter and decimal separator of the CSV format.
+pub fn write_fda_table_with_format(
+    path_buf: PathBuf,
+    machine: &str,
+    applicator: &str,
+    fda_table: &FdaTable,
+    format: &CsvFormat,
+) -> Result<(), EmuError> {
+    check_energies(&path_buf, &fda_table.energies)?;
+    let nc = fda_table.energies.len() + 2;
+    let mut row = vec!["Dimensions".to_owned(), "id".to_owned()];
+    row.extend(fda_table.energies.iter().map(|x| format_number(*x, format)));
+    let mut rows = vec![
+        label_row(machine, "", nc),
+        label_row("Applicator", applicator, nc),
+        row,
+    ];
+    for (i, (name, id)) in fda_table.names.iter().zip(fda_table.ids.iter()).enumerate() {
+        let mut row = vec![name.clone(), id.to_string()];
+        row.extend(
+            fda_table
+                .table
+                .iter()
+                .map(|col| format_number(col[i], format)),
+        );
+        rows.push(row);
+    }
+    write_rows(&path_buf, &rows, format)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -224,4 +433,117 @@ mod test {
         assert_eq!(fda_table.get_cf(8.0, 3).unwrap(), 1.7);
         assert_eq!(fda_table.get_cf(8.0, 10).unwrap(), 2.7);
     }
+
+    #[test]
+    fn fda_table_get_cf_ipol_energy() {
+        let fda_table = build_fda_table();
+        assert_eq!(fda_table.get_cf_ipol_energy(6.0, 3).unwrap(), 1.8);
+        assert!((fda_table.get_cf_ipol_energy(7.0, 3).unwrap() - 1.75).abs() < 1e-12);
+        assert!(fda_table.get_cf(7.0, 3).is_err());
+        assert!(fda_table.get_cf_ipol_energy(13.0, 3).is_err());
+        assert!(fda_table.get_cf_ipol_energy(7.0, 4).is_err());
+    }
+
+    #[test]
+    fn fda_table_get_cf_rectangle() {
+        let mut fda_table = FdaTable::new();
+        fda_table.set_energies(vec![6.0, 12.0]);
+        fda_table.add("10x10", 1, vec![1.0, 1.0]).unwrap();
+        fda_table.add("4x10", 2, vec![0.9, 0.99]).unwrap();
+        fda_table.add("2x10", 3, vec![0.8, 0.9]).unwrap();
+        fda_table.add("6x10 (AS=2cm)", 4, vec![0.5, 0.5]).unwrap();
+
+        // measured
+        let f = fda_table.get_cf_rectangle(6.0, 10.0, 4.0, false).unwrap();
+        assert_eq!(f.cf, 0.9);
+        assert!(!f.derived);
+
+        // CF(4x4) = 0.9^2, CF(2x2) = 0.8^2
+        let f = fda_table.get_cf_rectangle(6.0, 4.0, 2.0, false).unwrap();
+        assert!((f.cf - 0.72).abs() < 1e-12);
+        assert!(f.derived);
+        // CF(3x3) is interpolated between the square fields
+        let f = fda_table.get_cf_rectangle(6.0, 3.0, 10.0, false).unwrap();
+        assert!((f.cf - ((0.81 + 0.64) / 2.0_f64).sqrt()).abs() < 1e-12);
+
+        assert!(matches!(
+            fda_table.get_cf_rectangle(6.0, 1.0, 10.0, false),
+            Err(EmuError::Cutout(_))
+        ));
+        assert!(fda_table.get_cf_rectangle(9.0, 3.0, 10.0, false).is_err());
+        assert!(fda_table.get_cf_rectangle(9.0, 3.0, 10.0, true).is_ok());
+    }
+
+    #[test]
+    fn fda_table_get_cf_polygon() {
+        let mut fda_table = FdaTable::new();
+        fda_table.set_energies(vec![6.0]);
+        fda_table.add("2x2", 1, vec![0.8]).unwrap();
+        fda_table.add("4x4", 2, vec![0.9]).unwrap();
+        fda_table.add("10x10", 3, vec![1.0]).unwrap();
+
+        // circle with the area of a 4x4 square
+        let r = 4.0 / std::f64::consts::PI.sqrt();
+        let circle: Vec<(f64, f64)> = (0..720)
+            .map(|i| {
+                let a = i as f64 * std::f64::consts::PI / 360.0;
+                (r * a.cos(), r * a.sin())
+            })
+            .collect();
+        let cf = fda_table.get_cf_polygon(6.0, &circle, false).unwrap();
+        assert!((cf - 0.9).abs() < 1e-4, "{}", cf);
+
+        // a square is close to the factor of the square field
+        let square = vec![(-2.0, -2.0), (2.0, -2.0), (2.0, 2.0), (-2.0, 2.0)];
+        let cf = fda_table.get_cf_polygon(6.0, &square, false).unwrap();
+        assert!((cf - 0.9).abs() < 0.01, "{}", cf);
+        let f = fda_table
+            .get_cf_cutout(
+                6.0,
+                &Cutout::Polygon {
+                    points: square.clone(),
+                },
+                false,
+            )
+            .unwrap();
+        assert!(f.derived);
+
+        let shifted: Vec<(f64, f64)> = square.iter().map(|(x, y)| (x + 5.0, *y)).collect();
+        assert!(matches!(
+            fda_table.get_cf_polygon(6.0, &shifted, false),
+            Err(EmuError::Cutout(_))
+        ));
+        let small = vec![(-0.5, -0.5), (0.5, -0.5), (0.5, 0.5), (-0.5, 0.5)];
+        assert!(fda_table.get_cf_polygon(6.0, &small, false).is_err());
+    }
+
+    #[test]
+    fn fda_table_get_id() {
+        let fda_table = build_fda_table();
+        assert_eq!(fda_table.get_id("4x6"), Some(3));
+        assert_eq!(fda_table.get_id(" 4X4 "), Some(10));
+        assert_eq!(fda_table.get_id("5x5"), None);
+    }
+
+    #[test]
+    fn write_fda_table_round_trip() {
+        let fda_table = build_fda_table();
+        let dir =
+            std::env::temp_dir().join(format!("emu_check_fda_round_trip_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tmp = dir.join("fda.csv");
+        write_fda_table(tmp.clone(), "Synergy2", "6x6", &fda_table).unwrap();
+        let (machine, applicator, table) = read_fda_table(tmp.clone()).unwrap();
+        std::fs::remove_file(&tmp).unwrap();
+        assert_eq!(machine, "Synergy2");
+        assert_eq!(applicator, "6x6");
+        assert_eq!(table.names, fda_table.names);
+        assert_eq!(table.ids, fda_table.ids);
+        assert_eq!(table.energies, fda_table.energies);
+        assert_eq!(table.table, fda_table.table);
+
+        assert!(write_fda_table(tmp.clone(), "Synergy2", "6x6", &FdaTable::new()).is_err());
+        assert!(!tmp.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
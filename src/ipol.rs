@@ -1,3 +1,6 @@
+use crate::{EmuError, EnergyInterpolation};
+use serde::{Deserialize, Serialize};
+
 pub fn interpolate_linear(x: f64, x0: f64, x1: f64, y0: f64, y1: f64) -> f64 {
     // println!("x: {}", x);
     // println!("x0: {}", x0);
@@ -5,8 +8,331 @@ pub fn interpolate_linear(x: f64, x0: f64, x1: f64, y0: f64, y1: f64) -> f64 {
     // println!("y0: {}", y0);
     // println!("y1: {}", y1);
     let dx = x1 - x0;
-    if dx.abs() <= std::f64::EPSILON {
+    if dx.abs() <= f64::EPSILON {
         return y0;
     }
     y0 + (x - x0) * (y1 - y0) / dx
 }
+
+/// Indices of the tabulated values bracketing x (equal indices if x is tabulated),
+/// None if x is outside the tabulated range.
+pub fn find_neighbours(xs: &[f64], x: f64) -> Option<(usize, usize)> {
+    if let Some(i) = xs.iter().position(|v| (x - *v).abs() < f64::EPSILON) {
+        return Some((i, i));
+    }
+    let mut i0: Option<usize> = None;
+    let mut i1: Option<usize> = None;
+    for (i, v) in xs.iter().enumerate() {
+        if *v < x && i0.map_or(true, |j| xs[j] < *v) {
+            i0 = Some(i);
+        }
+        if *v > x && i1.map_or(true, |j| xs[j] > *v) {
+            i1 = Some(i);
+        }
+    }
+    Some((i0?, i1?))
+}
+
+/// Interpolation of tabulated values y(x).
+pub trait Interpolator {
+    /// Interpolate at x, None if x is outside the tabulated range.
+    /// The tabulated values don't need to be sorted.
+    fn interpolate(&self, xs: &[f64], ys: &[f64], x: f64) -> Option<f64>;
+}
+
+/// Interpolation method of the tabulated output factors between SSDs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpolationMethod {
+    #[default]
+    Linear,
+    LogLinear,
+    MonotoneCubic,
+    InverseSquare,
+    Nearest,
+}
+
+impl InterpolationMethod {
+    pub const ALL: [InterpolationMethod; 5] = [
+        InterpolationMethod::Linear,
+        InterpolationMethod::LogLinear,
+        InterpolationMethod::MonotoneCubic,
+        InterpolationMethod::InverseSquare,
+        InterpolationMethod::Nearest,
+    ];
+
+    pub fn interpolator(self) -> &'static dyn Interpolator {
+        match self {
+            InterpolationMethod::Linear => &LinearInterpolator,
+            InterpolationMethod::LogLinear => &LogLinearInterpolator,
+            InterpolationMethod::MonotoneCubic => &MonotoneCubicInterpolator,
+            InterpolationMethod::InverseSquare => &InverseSquareInterpolator,
+            InterpolationMethod::Nearest => &NearestInterpolator,
+        }
+    }
+}
+
+impl std::fmt::Display for InterpolationMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            InterpolationMethod::Linear => "linear",
+            InterpolationMethod::LogLinear => "log-linear",
+            InterpolationMethod::MonotoneCubic => "monotone cubic",
+            InterpolationMethod::InverseSquare => "inverse square",
+            InterpolationMethod::Nearest => "nearest",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Check that the tabulated values of x are numbers without duplicates, as required by the
+/// interpolators. The name of the values (e.g. SSDs) is used in the error.
+pub fn check_abscissae(name: &str, xs: &[f64]) -> Result<(), EmuError> {
+    if let Some(x) = xs.iter().find(|x| x.is_nan()) {
+        return Err(EmuError::Format(format!(
+            "The tabulated {} must be numbers [{}]",
+            name, x
+        )));
+    }
+    for (i, x) in xs.iter().enumerate() {
+        if xs[..i].contains(x) {
+            return Err(EmuError::Format(format!(
+                "The tabulated {} must be unique [{} is listed twice]",
+                name, x
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Tabulated points sorted by x, see check_abscissae.
+fn sorted_points(xs: &[f64], ys: &[f64]) -> Vec<(f64, f64)> {
+    let mut points: Vec<(f64, f64)> = xs.iter().cloned().zip(ys.iter().cloned()).collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    points
+}
+
+// Interpolate linearly after transforming the values, the transform is skipped
+// if a neighbouring value can't be transformed.
+fn interpolate_transformed(
+    xs: &[f64],
+    ys: &[f64],
+    x: f64,
+    valid: fn(f64) -> bool,
+    forward: fn(f64) -> f64,
+    inverse: fn(f64) -> f64,
+) -> Option<f64> {
+    let (i0, i1) = find_neighbours(xs, x)?;
+    let (y0, y1) = (ys[i0], ys[i1]);
+    if !valid(y0) || !valid(y1) {
+        return Some(interpolate_linear(x, xs[i0], xs[i1], y0, y1));
+    }
+    Some(inverse(interpolate_linear(
+        x,
+        xs[i0],
+        xs[i1],
+        forward(y0),
+        forward(y1),
+    )))
+}
+
+/// Linear interpolation between the neighbouring values.
+pub struct LinearInterpolator;
+
+impl Interpolator for LinearInterpolator {
+    fn interpolate(&self, xs: &[f64], ys: &[f64], x: f64) -> Option<f64> {
+        let (i0, i1) = find_neighbours(xs, x)?;
+        Some(interpolate_linear(x, xs[i0], xs[i1], ys[i0], ys[i1]))
+    }
+}
+
+/// Linear interpolation of ln(y), i.e. exponential between the neighbouring values.
+/// Falls back to linear interpolation for values that aren't positive.
+pub struct LogLinearInterpolator;
+
+impl Interpolator for LogLinearInterpolator {
+    fn interpolate(&self, xs: &[f64], ys: &[f64], x: f64) -> Option<f64> {
+        interpolate_transformed(xs, ys, x, |y| y > 0.0, f64::ln, f64::exp)
+    }
+}
+
+/// Linear interpolation of 1/sqrt(y), exact for values that follow the inverse square law.
+/// Falls back to linear interpolation for values that aren't positive.
+pub struct InverseSquareInterpolator;
+
+impl Interpolator for InverseSquareInterpolator {
+    fn interpolate(&self, xs: &[f64], ys: &[f64], x: f64) -> Option<f64> {
+        interpolate_transformed(
+            xs,
+            ys,
+            x,
+            |y| y > 0.0,
+            |y| 1.0 / y.sqrt(),
+            |v| 1.0 / (v * v),
+        )
+    }
+}
+
+/// Value of the nearest tabulated point, the lower one if x lies halfway.
+pub struct NearestInterpolator;
+
+impl Interpolator for NearestInterpolator {
+    fn interpolate(&self, xs: &[f64], ys: &[f64], x: f64) -> Option<f64> {
+        let (i0, i1) = find_neighbours(xs, x)?;
+        if (x - xs[i0]) <= (xs[i1] - x) {
+            Some(ys[i0])
+        } else {
+            Some(ys[i1])
+        }
+    }
+}
+
+/// Piecewise cubic Hermite interpolation with the slopes of Fritsch and Butland,
+/// which doesn't overshoot and preserves the monotonicity of the tabulated values.
+pub struct MonotoneCubicInterpolator;
+
+impl Interpolator for MonotoneCubicInterpolator {
+    fn interpolate(&self, xs: &[f64], ys: &[f64], x: f64) -> Option<f64> {
+        let (i0, i1) = find_neighbours(xs, x)?;
+        if i0 == i1 {
+            return Some(ys[i0]);
+        }
+        let points = sorted_points(xs, ys);
+        let n = points.len();
+        let k = points.iter().rposition(|(px, _)| *px <= x)?.min(n - 2);
+        let h: Vec<f64> = points.windows(2).map(|w| w[1].0 - w[0].0).collect();
+        let d: Vec<f64> = points
+            .windows(2)
+            .zip(h.iter())
+            .map(|(w, h)| (w[1].1 - w[0].1) / h)
+            .collect();
+        let slope = |i: usize| -> f64 {
+            if i == 0 {
+                d[0]
+            } else if i == n - 1 {
+                d[n - 2]
+            } else if d[i - 1] * d[i] <= 0.0 {
+                0.0
+            } else {
+                3.0 * (h[i - 1] + h[i])
+                    / ((2.0 * h[i] + h[i - 1]) / d[i - 1] + (h[i] + 2.0 * h[i - 1]) / d[i])
+            }
+        };
+        let t = (x - points[k].0) / h[k];
+        let (t2, t3) = (t * t, t * t * t);
+        Some(
+            (2.0 * t3 - 3.0 * t2 + 1.0) * points[k].1
+                + (t3 - 2.0 * t2 + t) * h[k] * slope(k)
+                + (-2.0 * t3 + 3.0 * t2) * points[k + 1].1
+                + (t3 - t2) * h[k] * slope(k + 1),
+        )
+    }
+}
+
+/// Get the column of a table (one column per energy) for an energy.
+/// If the energy isn't tabulated and energy interpolation is enabled, the column is
+/// interpolated linearly between the columns of the neighbouring energies.
+pub fn energy_column(
+    energies: &[f64],
+    table: &[Vec<f64>],
+    energy: f64,
+    energy_interpolation: bool,
+) -> Result<(Vec<f64>, Option<EnergyInterpolation>), EmuError> {
+    let (i0, i1) = find_neighbours(energies, energy).ok_or(EmuError::EnergyNotFound(energy))?;
+    if i0 != i1 && !energy_interpolation {
+        return Err(EmuError::EnergyNotFound(energy));
+    }
+    let (col0, col1) = match (table.get(i0), table.get(i1)) {
+        (Some(col0), Some(col1)) => (col0, col1),
+        _ => {
+            return Err(EmuError::Logic(
+                "Energy matching column in table was not found.".to_string(),
+            ))
+        }
+    };
+    if i0 == i1 {
+        return Ok((col0.clone(), None));
+    }
+    let (e0, e1) = (energies[i0], energies[i1]);
+    let col = col0
+        .iter()
+        .zip(col1.iter())
+        .map(|(y0, y1)| interpolate_linear(energy, e0, e1, *y0, *y1))
+        .collect();
+    Ok((
+        col,
+        Some(EnergyInterpolation {
+            energy0: e0,
+            energy1: e1,
+        }),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ipol_interpolators() {
+        let xs = [100.0, 95.0, 110.0];
+        // inverse square from a virtual source at 80 cm
+        let ys: Vec<f64> = xs.iter().map(|x| (80.0_f64 / (x - 15.0)).powi(2)).collect();
+        let exact = (80.0_f64 / 90.0).powi(2);
+        let ipol =
+            |method: InterpolationMethod, x: f64| method.interpolator().interpolate(&xs, &ys, x);
+
+        assert!((ipol(InterpolationMethod::InverseSquare, 105.0).unwrap() - exact).abs() < 1e-12);
+        assert_eq!(
+            ipol(InterpolationMethod::Linear, 105.0),
+            Some((ys[0] + ys[2]) / 2.0)
+        );
+        assert!(
+            (ipol(InterpolationMethod::LogLinear, 105.0).unwrap() - (ys[0] * ys[2]).sqrt()).abs()
+                < 1e-12
+        );
+        assert_eq!(ipol(InterpolationMethod::Nearest, 104.0), Some(ys[0]));
+        assert_eq!(ipol(InterpolationMethod::Nearest, 106.0), Some(ys[2]));
+        let cubic = ipol(InterpolationMethod::MonotoneCubic, 105.0).unwrap();
+        assert!(cubic < ys[0] && cubic > ys[2]);
+        assert!(
+            (cubic - exact).abs()
+                < (ipol(InterpolationMethod::Linear, 105.0).unwrap() - exact).abs()
+        );
+        for method in InterpolationMethod::ALL.iter() {
+            assert_eq!(ipol(*method, 95.0), Some(ys[1]), "{}", method);
+            assert_eq!(ipol(*method, 90.0), None, "{}", method);
+        }
+    }
+
+    #[test]
+    fn ipol_check_abscissae() {
+        assert!(check_abscissae("SSDs", &[100.0, 95.0, 110.0]).is_ok());
+        assert!(matches!(
+            check_abscissae("SSDs", &[100.0, f64::NAN, 110.0]),
+            Err(EmuError::Format(_))
+        ));
+        assert!(matches!(
+            check_abscissae("SSDs", &[100.0, 95.0, 100.0]),
+            Err(EmuError::Format(_))
+        ));
+    }
+
+    #[test]
+    fn ipol_energy_column() {
+        let energies = [4.0, 8.0, 6.0];
+        assert_eq!(find_neighbours(&energies, 6.0), Some((2, 2)));
+        assert_eq!(find_neighbours(&energies, 7.0), Some((2, 1)));
+        assert_eq!(find_neighbours(&energies, 9.0), None);
+
+        let table = vec![vec![1.0, 2.0], vec![3.0, 4.0], vec![2.0, 3.0]];
+        let (col, ipol) = energy_column(&energies, &table, 5.0, true).unwrap();
+        assert_eq!(col, vec![1.5, 2.5]);
+        let ipol = ipol.unwrap();
+        assert_eq!((ipol.energy0, ipol.energy1), (4.0, 6.0));
+        let (col, ipol) = energy_column(&energies, &table, 8.0, true).unwrap();
+        assert_eq!(col, vec![3.0, 4.0]);
+        assert!(ipol.is_none());
+        assert!(energy_column(&energies, &table, 5.0, false).is_err());
+        assert!(energy_column(&energies, &table, 3.0, true).is_err());
+    }
+}
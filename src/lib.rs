@@ -1,19 +1,59 @@
 // #![allow(dead_code)]
+mod batch;
+pub use batch::*;
+mod bolus;
+pub use bolus::*;
+mod calibration;
+pub use calibration::*;
+mod calc_options;
+pub use calc_options::*;
 mod calc_param;
 pub use calc_param::*;
 mod correction_data;
 pub use correction_data::*;
+mod energy_table;
+pub use energy_table::*;
 mod errors;
 pub use errors::*;
 mod fda_table;
 pub use fda_table::*;
 mod correction_data_set;
 pub use correction_data_set::*;
+mod correction_factor;
+pub use correction_factor::*;
+mod cutout;
+pub use cutout::*;
+mod dataset;
+pub use dataset::*;
+mod csv_format;
+pub use csv_format::*;
+mod dicom;
 mod ipol;
+pub use ipol::*;
+mod manifest;
+pub use manifest::*;
+mod obliquity_table;
+pub use obliquity_table::*;
 mod of_table;
 pub use of_table::*;
+mod pdd_table;
+pub use pdd_table::*;
+mod report;
+pub use report::*;
+mod rt_plan;
+pub use rt_plan::*;
+mod shield_table;
+pub use shield_table::*;
+mod tolerance;
+pub use tolerance::*;
+mod uncertainty;
+pub use uncertainty::*;
+mod validate;
+pub use validate::*;
 
 use console::Term;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 fn question(term: &Term, msg: &str) -> Result<String, EmuError> {
     if let Err(e) = term.write_str(&format!("{}: ", msg)) {
@@ -77,18 +117,40 @@ fn question_with_options<T: std::fmt::Display>(
     }
 }
 
+// Fail if a calculation parameter is missing and the user may not be asked for it.
+fn check_prompt(prompt: bool, field: &str) -> Result<(), EmuError> {
+    if prompt {
+        Ok(())
+    } else {
+        Err(EmuError::MissingInput(field.to_owned()))
+    }
+}
+
 /// Obtain the calculation parameters by interactively asking the user for input.
 /// The commandline questions are only asked if the corresponding input parameter doesn't contain
 /// the data.
 /// Return these parameters and the selected correction data based on those parameters.
-pub fn get_calc_param_input_cli<'a, 'b>(
+pub fn get_calc_param_input_cli<'a>(
+    vcd: &'a [CorrectionData],
+    opt_input_params: Option<&CalcParam>,
+) -> Result<(CalcParam, &'a CorrectionData), EmuError> {
+    get_calc_param_input(vcd, opt_input_params, true)
+}
+
+/// Obtain the calculation parameters from the input parameters.
+/// If `prompt` is true, missing parameters are asked interactively,
+/// otherwise a missing parameter results in an error.
+/// The depth of zref is derived from the selected energy if it isn't set.
+/// Return these parameters and the selected correction data based on those parameters.
+pub fn get_calc_param_input<'a>(
     vcd: &'a [CorrectionData],
-    opt_input_params: Option<&'b CalcParam>,
+    opt_input_params: Option<&CalcParam>,
+    prompt: bool,
 ) -> Result<(CalcParam, &'a CorrectionData), EmuError> {
     let mut calc_param = CalcParam::new();
     let has_opt_input_param = opt_input_params.is_some();
     // Check if multiple machines are present
-    let term = Term::stdout();
+    let term = Term::stderr();
     let mut machines = vec![];
     for cd in vcd {
         if !machines.contains(&cd.machine) {
@@ -103,6 +165,7 @@ pub fn get_calc_param_input_cli<'a, 'b>(
     } else if has_opt_input_param && opt_input_params.unwrap().has_machine() {
         calc_param.machine = opt_input_params.unwrap().machine.clone();
     } else {
+        check_prompt(prompt, "machine")?;
         let idx = question_with_options(&term, "Machine", &machines)?;
         calc_param.machine = machines.get(idx).unwrap().clone();
         if let Err(e) = term.write_line("") {
@@ -125,6 +188,7 @@ pub fn get_calc_param_input_cli<'a, 'b>(
     } else if has_opt_input_param && opt_input_params.unwrap().has_applicator() {
         calc_param.applicator = opt_input_params.unwrap().applicator.clone();
     } else {
+        check_prompt(prompt, "applicator")?;
         let idx = question_with_options(&term, "Applicator[cm x cm]", &vapp)?;
         calc_param.applicator = vapp.get(idx).unwrap().clone();
         if let Err(e) = term.write_line("") {
@@ -175,14 +239,16 @@ pub fn get_calc_param_input_cli<'a, 'b>(
         return Err(EmuError::Str(
             "No energy found in the filtered correction data".to_owned(),
         ));
-    } else if has_opt_input_param
-        && opt_input_params.unwrap().has_energy()
-        && opt_input_params.unwrap().has_depth_zref()
-    {
+    } else if has_opt_input_param && opt_input_params.unwrap().has_energy() {
         let tinput_param = opt_input_params.unwrap();
         calc_param.energy = tinput_param.energy;
-        calc_param.depth_zref = tinput_param.depth_zref;
+        if tinput_param.has_depth_zref() {
+            calc_param.depth_zref = tinput_param.depth_zref;
+        } else if let Some(zref) = cd.get_zref(calc_param.energy) {
+            calc_param.depth_zref = zref;
+        }
     } else {
+        check_prompt(prompt, "energy")?;
         let idx = question_with_options(&term, "Energy[MeV]", &venergy)?;
         calc_param.energy = *venergy.get(idx).unwrap();
         calc_param.depth_zref = *vzref.get(idx).unwrap();
@@ -191,7 +257,8 @@ pub fn get_calc_param_input_cli<'a, 'b>(
         }
     }
 
-    if !venergy.contains(&calc_param.energy) {
+    // A non-tabulated energy is only valid if energy interpolation is enabled.
+    if cd.get_zref(calc_param.energy).is_none() {
         return Err(EmuError::Str("No valid energy was selected".to_owned()));
     }
 
@@ -207,9 +274,13 @@ pub fn get_calc_param_input_cli<'a, 'b>(
         return Err(EmuError::Str(
             "No FDA IDs found in filtered correction data".to_owned(),
         ));
-    } else if opt_input_params.is_some() && opt_input_params.unwrap().has_fda_id() {
+    } else if has_opt_input_param && opt_input_params.unwrap().has_cutout() {
+        // The correction factor of the cutout is derived from the FDA table.
+        calc_param.cutout = opt_input_params.unwrap().cutout.clone();
+    } else if has_opt_input_param && opt_input_params.unwrap().has_fda_id() {
         calc_param.fda_id = opt_input_params.unwrap().fda_id;
     } else {
+        check_prompt(prompt, "fda_id")?;
         let idx = question_with_options(&term, "FDA", &vfda)?;
         calc_param.fda_id = *cd.fda.ids.get(idx).unwrap();
         if let Err(e) = term.write_line("") {
@@ -220,6 +291,7 @@ pub fn get_calc_param_input_cli<'a, 'b>(
     if has_opt_input_param && opt_input_params.unwrap().has_ssd() {
         calc_param.ssd = opt_input_params.unwrap().ssd;
     } else {
+        check_prompt(prompt, "ssd")?;
         // Get source to skin distance
         calc_param.ssd = question_parse_res(&term, "SSD[cm]")?;
         if let Err(e) = term.write_line("") {
@@ -227,13 +299,29 @@ pub fn get_calc_param_input_cli<'a, 'b>(
         }
     }
 
-    if has_opt_input_param && opt_input_params.unwrap().has_dose_zref() {
+    // The optional parameters aren't asked: the calculation date defaults to today,
+    // the beam is assumed to be perpendicular to the skin, without bolus or internal shield.
+    if let Some(input_params) = opt_input_params {
+        calc_param.calc_date = input_params.calc_date;
+        calc_param.incidence_angle = input_params.incidence_angle;
+        calc_param.bolus_thickness = input_params.bolus_thickness;
+        calc_param.bolus_water_equivalence = input_params.bolus_water_equivalence;
+        calc_param.shield_depth = input_params.shield_depth;
+    }
+
+    if has_opt_input_param && opt_input_params.unwrap().has_prescription() {
+        // The dose at zref is derived from the dose prescribed at another depth.
+        calc_param.prescription_dose = opt_input_params.unwrap().prescription_dose;
+        calc_param.prescription_depth = opt_input_params.unwrap().prescription_depth;
+        calc_param = cd.resolve_prescription(&calc_param)?.0;
+    } else if has_opt_input_param && opt_input_params.unwrap().has_dose_zref() {
         calc_param.dose_zref = opt_input_params.unwrap().dose_zref;
     } else {
+        check_prompt(prompt, "dose_zref")?;
         // Get the dose at depth zref
         calc_param.dose_zref = question_parse_res(
             &term,
-            &*format!("Dose[cGy] (zref: {} cm)", calc_param.depth_zref),
+            &format!("Dose[cGy] (zref: {} cm)", calc_param.depth_zref),
         )?;
         if let Err(e) = term.write_line("") {
             return Err(EmuError::Terminal(e.to_string()));
@@ -243,6 +331,7 @@ pub fn get_calc_param_input_cli<'a, 'b>(
     if has_opt_input_param && opt_input_params.unwrap().has_planned_beam_mu() {
         calc_param.planned_beam_mu = opt_input_params.unwrap().planned_beam_mu;
     } else {
+        check_prompt(prompt, "planned_beam_mu")?;
         // Get the planned MUs in the plan for the beam that's being verified.
         calc_param.planned_beam_mu = question_parse_res(&term, "Planned beam MUs")?;
         if let Err(e) = term.write_line("") {
@@ -253,9 +342,112 @@ pub fn get_calc_param_input_cli<'a, 'b>(
     Ok((calc_param, cd))
 }
 
+/// Relative difference [%] between the planned MUs and the MUs of the check calculation.
+pub fn mu_difference(planned_beam_mu: f64, mu: f64) -> f64 {
+    (1.0 - (planned_beam_mu / mu)) * 100.0
+}
+
+/// Compute the MUs and the correction factors used in the calculation.
+/// Factors registered on a CorrectionDataSet are only applied by CorrectionDataSet::compute,
+/// calc and predict_dose.
+pub fn compute(calc_param: &CalcParam, cd: &CorrectionData) -> Result<Computed, EmuError> {
+    compute_with_factors(calc_param, cd, &[])
+}
+
+/// Compute the MUs, applying the given correction factors after the output factor, the FDA
+/// factor and the built-in correction factors (see builtin_factors).
+pub fn compute_with_factors(
+    calc_param: &CalcParam,
+    cd: &CorrectionData,
+    factors: &[Arc<dyn CorrectionFactor>],
+) -> Result<Computed, EmuError> {
+    let (mut calc_param, pdd_conversion) = cd.resolve_prescription(calc_param)?;
+    let calc_date = *calc_param.calc_date.get_or_insert_with(today);
+    let calc_param = &calc_param;
+    let calibration = cd.get_calibration(calc_param.energy, calc_date)?;
+    let cgy_per_mu = calibration.as_ref().map_or(1.0, |c| c.cgy_per_mu);
+    // The output factor is looked up at the SSD to the bolus surface.
+    let bolus = calc_param.bolus_adjustment()?;
+    let mut lookup = calc_param.clone();
+    if let Some(b) = &bolus {
+        lookup.ssd = b.ssd;
+    }
+    let of_ipol = cd.get_of_ipol(lookup.energy, lookup.ssd, lookup.depth_zref)?;
+    let fda = cd.get_fda_factor(calc_param)?;
+    let cf_fda = fda.cf;
+    let cf_of = of_ipol.cf;
+    let mut applied_factors = apply_factors(&builtin_factors(), calc_param, cd)?;
+    applied_factors.extend(apply_factors(factors, calc_param, cd)?);
+    let cf_other = cgy_per_mu * cf_fda * applied_factors.iter().map(|f| f.value).product::<f64>();
+    let mu = calc_param.dose_zref / (cf_of * cf_other);
+    let interpolation_comparison = if cd.options.compare_interpolation && of_ipol.method.is_some() {
+        compare_interpolation(&lookup, cd, cf_other, mu)?
+    } else {
+        vec![]
+    };
+    Ok(Computed {
+        calc_param: calc_param.clone(),
+        cf_of,
+        cf_fda,
+        cf_fda_derived: fda.derived,
+        factors: applied_factors,
+        of_ipol,
+        mu,
+        difference: mu_difference(calc_param.planned_beam_mu, mu),
+        interpolation_comparison,
+        uncertainty: cd.get_mu_uncertainty(&lookup, mu)?,
+        pdd_conversion,
+        calibration,
+        bolus,
+    })
+}
+
+// Compute the MUs with every method to interpolate the output factors between SSDs.
+// The SSD of the calculation parameters is the SSD to the bolus surface.
+fn compare_interpolation(
+    calc_param: &CalcParam,
+    cd: &CorrectionData,
+    cf_other: f64, // product of the calibration and the factors other than the output factor
+    mu: f64,
+) -> Result<Vec<InterpolationComparison>, EmuError> {
+    let mut v = vec![];
+    for method in InterpolationMethod::ALL.iter() {
+        let cf_of = cd
+            .output_factors
+            .get_cf_ipol_with(
+                calc_param.energy,
+                calc_param.ssd,
+                cd.options.energy_interpolation,
+                *method,
+            )?
+            .cf;
+        let method_mu = calc_param.dose_zref / (cf_of * cf_other);
+        v.push(InterpolationComparison {
+            method: *method,
+            cf_of,
+            mu: method_mu,
+            difference: (method_mu / mu - 1.0) * 100.0,
+        });
+    }
+    Ok(v)
+}
+
 pub fn calculate_mu(calc_param: &CalcParam, cd: &CorrectionData) -> Result<f64, EmuError> {
-    let f = cd.get_correction_factor(calc_param.energy, calc_param.ssd, calc_param.fda_id)?;
-    Ok(calc_param.dose_zref / f)
+    Ok(compute(calc_param, cd)?.mu)
+}
+
+/// Reverse calculation: the dose [cGy] at zref predicted by the tables for the planned MUs.
+/// The planned dose isn't needed.
+pub fn calculate_dose(calc_param: &CalcParam, cd: &CorrectionData) -> Result<f64, EmuError> {
+    compute(calc_param, cd)?.predicted_dose()
+}
+
+/// Predict the dose at zref for the planned MUs and compare it with the planned dose.
+pub fn predict_dose(
+    calc_param: &CalcParam,
+    cd: &CorrectionData,
+) -> Result<DosePrediction, EmuError> {
+    compute(calc_param, cd)?.dose_prediction()
 }
 
 pub async fn load_data_calc_mu(
@@ -264,11 +456,48 @@ pub async fn load_data_calc_mu(
 ) -> Result<(f64, CalcParam), EmuError> {
     let vcd = load_data(dirname).await?;
     let (calc_param, correction_data) = get_calc_param_input_cli(&vcd, opt_input_params)?;
-    let mu = calculate_mu(&calc_param, &correction_data)?;
+    let mu = calculate_mu(&calc_param, correction_data)?;
     Ok((mu, calc_param))
 }
 
+/// Load the configuration data and compute the MUs.
+/// Calculation parameters missing from the input parameters are asked interactively
+/// if `prompt` is true, otherwise they result in an error.
+pub async fn load_data_compute(
+    dirname: &str,
+    opt_input_params: Option<&CalcParam>,
+    prompt: bool,
+) -> Result<Computed, EmuError> {
+    let vcd = load_data(dirname).await?;
+    let (calc_param, correction_data) = get_calc_param_input(&vcd, opt_input_params, prompt)?;
+    compute(&calc_param, correction_data)
+}
+
+/// Load the configuration data and compute the MUs of each electron beam in a DICOM RT Plan.
+/// Calculation parameters missing from the plan are asked interactively if `prompt` is true.
+/// A beam that can't be mapped onto the correction data doesn't prevent
+/// the calculation of the other beams.
+pub async fn load_data_compute_rt_plan(
+    dirname: &str,
+    rt_plan: &str,
+    prompt: bool,
+) -> Result<Vec<Result<Computed, EmuError>>, EmuError> {
+    let vcd = load_data(dirname).await?;
+    let beams = read_rt_plan(PathBuf::from(rt_plan))?;
+    let mut v = Vec::with_capacity(beams.len());
+    for beam in &beams {
+        let res = beam.to_calc_param(&vcd).and_then(|input_params| {
+            let (calc_param, correction_data) =
+                get_calc_param_input(&vcd, Some(&input_params), prompt)?;
+            compute(&calc_param, correction_data)
+        });
+        v.push(res);
+    }
+    Ok(v)
+}
+
 #[cfg(test)]
+#[allow(clippy::excessive_precision)]
 mod test {
     use super::*;
 
@@ -288,6 +517,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 110.841642761819,
             fda_id: 10,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -298,6 +528,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 98.400015744002500,
             fda_id: 10,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -308,6 +539,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 99.454986673031800,
             fda_id: 10,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -318,6 +550,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 161.969549724652000,
             fda_id: 10,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -328,6 +561,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 158.153776079558000,
             fda_id: 10,
+            ..CalcParam::new()
         });
 
         vcp.push(CalcParam {
@@ -339,6 +573,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 118.990956687292000,
             fda_id: 10,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -349,6 +584,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 104.931794333683000,
             fda_id: 10,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -359,6 +595,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 105.820105820106000,
             fda_id: 10,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -369,6 +606,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 187.617260787992000,
             fda_id: 10,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -379,6 +617,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 182.149362477231000,
             fda_id: 10,
+            ..CalcParam::new()
         });
 
         //
@@ -394,6 +633,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 118.046388925549000,
             fda_id: 9,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -404,6 +644,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 109.051254089422000,
             fda_id: 9,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -414,6 +655,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 109.819994047756000,
             fda_id: 9,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -424,6 +666,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 164.729991071634000,
             fda_id: 9,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -434,6 +677,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 161.366164678044000,
             fda_id: 9,
+            ..CalcParam::new()
         });
 
         vcp.push(CalcParam {
@@ -445,6 +689,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 110.577390904346000,
             fda_id: 9,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -455,6 +700,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 102.040816326531000,
             fda_id: 9,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -465,6 +711,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 103.071531642960000,
             fda_id: 9,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -475,6 +722,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 157.713781030186000,
             fda_id: 9,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -485,6 +733,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 154.186788042506000,
             fda_id: 9,
+            ..CalcParam::new()
         });
 
         //
@@ -500,6 +749,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 110.815602836879000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -510,6 +760,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 102.774922918808000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -520,6 +771,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 104.384133611691000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -530,6 +782,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 151.515151515152000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -540,6 +793,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 148.544266191325000,
             fda_id: 5,
+            ..CalcParam::new()
         });
 
         vcp.push(CalcParam {
@@ -551,6 +805,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 109.601052170101000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -561,6 +816,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 100.806451612903000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -571,6 +827,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 101.832993890020000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -581,6 +838,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 152.207001522070000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -591,6 +849,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 148.986889153754000,
             fda_id: 5,
+            ..CalcParam::new()
         });
 
         //
@@ -606,6 +865,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 111.656989727557000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -616,6 +876,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 102.880658436214000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -626,6 +887,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 103.950103950104000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -636,6 +898,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 150.602409638554000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -646,6 +909,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 147.666863555818000,
             fda_id: 5,
+            ..CalcParam::new()
         });
 
         vcp.push(CalcParam {
@@ -657,6 +921,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 108.530497069677000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -667,6 +932,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 99.502487562189100,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -677,6 +943,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 100.704934541793000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -687,6 +954,7 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 148.148148148148000,
             fda_id: 5,
+            ..CalcParam::new()
         });
         vcp.push(CalcParam {
             machine: "Synergy2".to_string(),
@@ -697,19 +965,412 @@ mod test {
             dose_zref: 100.0,
             planned_beam_mu: 145.137880986938000,
             fda_id: 5,
+            ..CalcParam::new()
         });
 
         for cp in &vcp {
-            let res = async_std::task::block_on(load_data_calc_mu("resources", Some(&cp)));
+            let res = async_std::task::block_on(load_data_calc_mu("resources", Some(cp)));
             assert!(res.is_ok());
             let (mu_man, tcp) = res.unwrap();
             assert!(
-                (mu_man - cp.planned_beam_mu).abs() < std::f32::EPSILON as f64,
-                format!(
-                    "CalcParam:{}\nMU[man]={:.15} != MU[plan]={:.15}",
-                    tcp, mu_man, cp.planned_beam_mu
-                )
+                (mu_man - cp.planned_beam_mu).abs() < f32::EPSILON as f64,
+                "CalcParam:{}\nMU[man]={:.15} != MU[plan]={:.15}",
+                tcp,
+                mu_man,
+                cp.planned_beam_mu
             );
         }
     }
+
+    #[test]
+    fn test_calc_param_no_prompt() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let mut cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 6.0,
+            ssd: 95.0,
+            depth_zref: 0.0,
+            dose_zref: 100.0,
+            planned_beam_mu: 102.040816326531000,
+            fda_id: 9,
+            ..CalcParam::new()
+        };
+        let (tcp, cd) = get_calc_param_input(&vcd, Some(&cp), false).unwrap();
+        assert_eq!(tcp.depth_zref, 1.36);
+        assert_eq!(cd.applicator, "10x10");
+
+        cp.dose_zref = 0.0;
+        match get_calc_param_input(&vcd, Some(&cp), false) {
+            Err(EmuError::MissingInput(field)) => assert_eq!(field, "dose_zref"),
+            _ => panic!("Expected a missing dose"),
+        }
+    }
+
+    #[test]
+    fn test_compute_virtual_source() {
+        let mut vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let cd = vcd.iter_mut().find(|cd| cd.applicator == "10x10").unwrap();
+        let mut cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 12.0,
+            ssd: 99.2,
+            depth_zref: 2.78,
+            dose_zref: 100.0,
+            planned_beam_mu: 118.046388925549,
+            fda_id: 9,
+            ..CalcParam::new()
+        };
+        let tabulated = compute(&cp, cd).unwrap();
+        assert!(tabulated.of_ipol.virtual_source.is_none());
+
+        cd.options.ssd_model = SsdModel::VirtualSource;
+        let computed = compute(&cp, cd).unwrap();
+        let fit = computed.of_ipol.virtual_source.as_ref().unwrap();
+        assert_eq!(fit.ssd_ref, 95.0);
+        assert_eq!(fit.residuals.len(), 10);
+        // the measured data deviates up to about 1% from the inverse square law
+        assert!(fit.max_residual() < 0.015);
+        assert!((computed.cf_of - tabulated.cf_of).abs() < 0.01);
+
+        cp.ssd = 118.0;
+        assert!(matches!(compute(&cp, cd), Err(EmuError::SSDNotFound(_))));
+        cd.options.ssd_extrapolation = 5.0;
+        let computed = compute(&cp, cd).unwrap();
+        assert!(computed.of_ipol.virtual_source.unwrap().extrapolated);
+        assert!(computed.cf_of < 0.662);
+    }
+
+    #[test]
+    fn test_compute_interpolation_method() {
+        let mut vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let cd = vcd.iter_mut().find(|cd| cd.applicator == "10x10").unwrap();
+        // between the sparse SSD rows 105 and 110
+        let cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 12.0,
+            ssd: 107.5,
+            depth_zref: 2.78,
+            dose_zref: 100.0,
+            planned_beam_mu: 130.0,
+            fda_id: 1,
+            ..CalcParam::new()
+        };
+        let linear = compute(&cp, cd).unwrap();
+        assert!(linear.interpolation_comparison.is_empty());
+        assert!((linear.cf_of - (0.812 + 0.731) / 2.0).abs() < 1e-12);
+
+        cd.options
+            .machines
+            .entry("Synergy2".to_owned())
+            .or_default()
+            .ssd_interpolation = Some(InterpolationMethod::Nearest);
+        cd.options.compare_interpolation = true;
+        let nearest = compute(&cp, cd).unwrap();
+        assert_eq!(nearest.of_ipol.method, Some(InterpolationMethod::Nearest));
+        assert_eq!(nearest.cf_of, 0.812);
+        assert_eq!(nearest.interpolation_comparison.len(), 5);
+        let c = &nearest.interpolation_comparison[0];
+        assert_eq!(c.method, InterpolationMethod::Linear);
+        assert!((c.mu - linear.mu).abs() < 1e-9);
+        assert!(c.difference > 0.0);
+    }
+
+    #[test]
+    fn test_compute_obliquity() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let mut cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 6.0,
+            ssd: 95.0,
+            depth_zref: 1.36,
+            dose_zref: 100.0,
+            planned_beam_mu: 100.0,
+            fda_id: 1,
+            ..CalcParam::new()
+        };
+        let cd = vcd.iter().find(|cd| cd.applicator == "10x10").unwrap();
+        let perpendicular = compute(&cp, cd).unwrap();
+        assert!(perpendicular.factor("obliquity").is_none());
+
+        cp.incidence_angle = Some(45.0);
+        let oblique = compute(&cp, cd).unwrap();
+        assert_eq!(oblique.factor("obliquity"), Some(1.029));
+        assert!((oblique.mu * 1.029 - perpendicular.mu).abs() < 1e-9);
+        cp.incidence_angle = Some(90.0);
+        assert!(matches!(
+            compute(&cp, cd),
+            Err(EmuError::InvalidInput(_, _))
+        ));
+
+        // no obliquity table for the 6x6 applicator, unless the incidence is perpendicular
+        let cd6 = vcd.iter().find(|cd| cd.applicator == "6x6").unwrap();
+        cp.applicator = "6x6".to_string();
+        cp.incidence_angle = Some(30.0);
+        assert!(matches!(
+            compute(&cp, cd6),
+            Err(EmuError::ObliquityTableNotFound(_, _))
+        ));
+        cp.incidence_angle = Some(0.0);
+        assert!(compute(&cp, cd6).unwrap().factor("obliquity").is_none());
+    }
+
+    #[test]
+    fn test_compute_bolus() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let cd = vcd.iter().find(|cd| cd.applicator == "10x10").unwrap();
+        let mut cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 12.0,
+            ssd: 100.0,
+            depth_zref: 2.78,
+            dose_zref: 100.0,
+            planned_beam_mu: 100.0,
+            fda_id: 1,
+            bolus_thickness: Some(1.0),
+            ..CalcParam::new()
+        };
+        let computed = compute(&cp, cd).unwrap();
+        let bolus = computed.bolus.unwrap();
+        assert_eq!(bolus.ssd, 99.0);
+        assert!((bolus.depth_zref - 1.78).abs() < 1e-12);
+        assert_eq!(computed.calc_param.ssd, 100.0);
+        assert_eq!(
+            computed.cf_of,
+            cd.output_factors.get_cf(12.0, 99.0).unwrap()
+        );
+        // only the SSD to the bolus surface must be tabulated (95 - 115 cm)
+        cp.ssd = 115.5;
+        let computed = compute(&cp, cd).unwrap();
+        assert_eq!(
+            computed.cf_of,
+            cd.output_factors.get_cf(12.0, 114.5).unwrap()
+        );
+        let mut cd_compare = cd.clone();
+        cd_compare
+            .options
+            .machines
+            .entry("Synergy2".to_owned())
+            .or_default()
+            .ssd_interpolation = Some(InterpolationMethod::Linear);
+        cd_compare.options.compare_interpolation = true;
+        let compared = compute(&cp, &cd_compare).unwrap();
+        let c = &compared.interpolation_comparison[0];
+        assert_eq!(c.method, InterpolationMethod::Linear);
+        assert_eq!(c.cf_of, compared.cf_of);
+        assert!((c.mu - compared.mu).abs() < 1e-9);
+        cp.ssd = 100.0;
+
+        // 100 cGy at 1.5 cm below the skin is at dmax (2.5 cm) below the bolus surface
+        cp.dose_zref = 0.0;
+        cp.prescription_dose = Some(100.0);
+        cp.prescription_depth = Some(1.5);
+        let computed = compute(&cp, cd).unwrap();
+        assert_eq!(computed.pdd_conversion.unwrap().pdd_depth, 100.0);
+    }
+
+    #[test]
+    fn test_compute_shield() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let cd = vcd.iter().find(|cd| cd.applicator == "10x10").unwrap();
+        let mut cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 6.0,
+            ssd: 95.0,
+            depth_zref: 1.36,
+            dose_zref: 100.0,
+            planned_beam_mu: 100.0,
+            fda_id: 1,
+            ..CalcParam::new()
+        };
+        let unshielded = compute(&cp, cd).unwrap();
+        assert!(unshielded.factor("shield").is_none());
+
+        cp.shield_depth = Some(1.5);
+        let shielded = compute(&cp, cd).unwrap();
+        assert_eq!(shielded.factor("shield"), Some(1.629));
+        assert!((shielded.mu * 1.629 - unshielded.mu).abs() < 1e-9);
+        // with bolus the shield lies deeper below the bolus surface
+        cp.bolus_thickness = Some(0.5);
+        cp.ssd = 95.5;
+        assert_eq!(compute(&cp, cd).unwrap().factor("shield"), Some(1.662));
+        // a shield upstream of zref (1.36 cm) is refused
+        cp.bolus_thickness = None;
+        cp.ssd = 95.0;
+        cp.shield_depth = Some(1.0);
+        assert!(matches!(
+            compute(&cp, cd),
+            Err(EmuError::InvalidInput(_, _))
+        ));
+        cp.shield_depth = Some(1.5);
+
+        let cd6 = vcd.iter().find(|cd| cd.applicator == "6x6").unwrap();
+        assert!(matches!(
+            compute(&cp, cd6),
+            Err(EmuError::ShieldTableNotFound(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_calculate_dose() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let cd = vcd.iter().find(|cd| cd.applicator == "10x10").unwrap();
+        let mut cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 12.0,
+            ssd: 99.2,
+            depth_zref: 2.78,
+            dose_zref: 100.0,
+            planned_beam_mu: 118.046388925549,
+            fda_id: 9,
+            ..CalcParam::new()
+        };
+        assert!((calculate_dose(&cp, cd).unwrap() - 100.0).abs() < 1e-9);
+
+        // 5% fewer MUs deliver 5% less dose
+        cp.planned_beam_mu *= 0.95;
+        let prediction = predict_dose(&cp, cd).unwrap();
+        assert!((prediction.predicted_dose - 95.0).abs() < 1e-9);
+        assert!((prediction.deviation + 5.0).abs() < 1e-9);
+        assert!((prediction.relative_deviation + 5.0).abs() < 1e-9);
+
+        // the predicted dose doesn't need the planned dose, the deviation does
+        cp.dose_zref = 0.0;
+        assert!((calculate_dose(&cp, cd).unwrap() - 95.0).abs() < 1e-9);
+        assert!(matches!(
+            predict_dose(&cp, cd),
+            Err(EmuError::MissingInput(_))
+        ));
+        cp.dose_zref = 100.0;
+        cp.planned_beam_mu = 0.0;
+        assert!(matches!(
+            predict_dose(&cp, cd),
+            Err(EmuError::MissingInput(_))
+        ));
+
+        // at oblique incidence and with a calibration, D = MU(plan) · cGy/MU · CF_OF · CF_other
+        let mut cd = cd.clone();
+        cd.calibrations = vec![Calibration {
+            machine: "Synergy2".to_string(),
+            energy: 12.0,
+            cgy_per_mu: 1.02,
+            effective_date: chrono::NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(),
+        }];
+        cp.planned_beam_mu = 100.0;
+        cp.incidence_angle = Some(45.0);
+        let computed = compute(&cp, &cd).unwrap();
+        let expected = 100.0 * 1.02 * computed.cf_of * computed.cf_fda * 1.017;
+        assert!((calculate_dose(&cp, &cd).unwrap() - expected).abs() < 1e-9);
+        let prediction = predict_dose(&cp, &cd).unwrap();
+        assert!((prediction.predicted_dose - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_compute_calibration() {
+        let mut vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let cd = vcd.iter_mut().find(|cd| cd.applicator == "10x10").unwrap();
+        let mut cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 12.0,
+            ssd: 95.0,
+            depth_zref: 2.78,
+            dose_zref: 100.0,
+            planned_beam_mu: 100.0,
+            fda_id: 1,
+            ..CalcParam::new()
+        };
+        let uncalibrated = compute(&cp, cd).unwrap();
+        assert!(uncalibrated.calibration.is_none());
+        assert_eq!(uncalibrated.calc_param.calc_date, Some(today()));
+
+        let date = |y, m, d| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let calibration = |cgy_per_mu, effective_date| Calibration {
+            machine: "Synergy2".to_owned(),
+            energy: 12.0,
+            cgy_per_mu,
+            effective_date,
+        };
+        cd.calibrations = vec![
+            calibration(1.0, date(2020, 1, 1)),
+            calibration(1.02, date(2021, 6, 1)),
+        ];
+        cp.calc_date = Some(date(2021, 5, 31));
+        let computed = compute(&cp, cd).unwrap();
+        assert_eq!(computed.calibration.unwrap().cgy_per_mu, 1.0);
+        assert!((computed.mu - uncalibrated.mu).abs() < 1e-12);
+        cp.calc_date = Some(date(2021, 6, 1));
+        let computed = compute(&cp, cd).unwrap();
+        assert_eq!(computed.calibration.unwrap().cgy_per_mu, 1.02);
+        assert!((computed.mu * 1.02 - uncalibrated.mu).abs() < 1e-9);
+        cp.calc_date = Some(date(2019, 1, 1));
+        assert!(matches!(
+            compute(&cp, cd),
+            Err(EmuError::CalibrationNotFound(_, _, _))
+        ));
+    }
+
+    #[test]
+    fn test_compute_prescription_depth() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        // 100 cGy at dmax (PDD 100% at 2.5 cm), zref is 2.78 cm
+        let cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 12.0,
+            ssd: 95.0,
+            depth_zref: 0.0,
+            dose_zref: 0.0,
+            planned_beam_mu: 99.5,
+            fda_id: 1,
+            prescription_dose: Some(100.0),
+            prescription_depth: Some(2.5),
+            ..CalcParam::new()
+        };
+        let (tcp, cd) = get_calc_param_input(&vcd, Some(&cp), false).unwrap();
+        let pdd_zref = 100.0 + (99.0 - 100.0) * (2.78 - 2.5) / 0.5;
+        assert!((tcp.dose_zref - pdd_zref).abs() < 1e-9);
+        let computed = compute(&tcp, cd).unwrap();
+        assert!((computed.mu - pdd_zref).abs() < 1e-9);
+        assert_eq!(computed.pdd_conversion.unwrap().pdd_depth, 100.0);
+
+        // no PDD table for the 6x6 applicator
+        let cd6 = vcd.iter().find(|cd| cd.applicator == "6x6").unwrap();
+        assert!(matches!(
+            compute(&cp, cd6),
+            Err(EmuError::PddTableNotFound(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_calc_mu_polygon_cutout() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 12.0,
+            ssd: 95.0,
+            depth_zref: 2.78,
+            dose_zref: 100.0,
+            planned_beam_mu: 112.0,
+            fda_id: usize::MAX,
+            cutout: Some(Cutout::Polygon {
+                points: vec![(-1.5, -1.5), (1.5, -1.5), (1.5, 1.5), (-1.5, 1.5)],
+            }),
+            ..CalcParam::new()
+        };
+        let (tcp, cd) = get_calc_param_input(&vcd, Some(&cp), false).unwrap();
+        // CF(3x3) = CF(3x10)^2 / CF(10x10) = 0.943^2
+        let mu = calculate_mu(&tcp, cd).unwrap();
+        let mu_square = 100.0 / (0.943 * 0.943);
+        assert!((mu - mu_square).abs() / mu_square < 0.02, "{}", mu);
+        let computed = compute(&tcp, cd).unwrap();
+        assert!(computed.cf_fda_derived);
+    }
 }
@@ -1,11 +1,18 @@
 #![allow(unused_imports)]
 use async_std::prelude::*;
 use async_std::task;
-use clap::{crate_authors, crate_description, crate_version, App, Arg};
+use chrono::NaiveDate;
+use clap::{crate_authors, crate_description, crate_version, App, Arg, ArgMatches, SubCommand};
 use console::Term;
-use emu_check::{load_data_calc_mu, EmuError};
+use emu_check::{
+    convert_data_to_dataset, convert_dataset_to_data, create_manifest, load_data_batch,
+    load_data_compute, load_data_compute_rt_plan, load_tolerance_table, read_cutout,
+    require_verified_manifest, validate_data, write_manifest, CalcParam, CalcReport, Cutout,
+    DatasetIdentity, EmuError, ReferenceConditions, Verdict, MANIFEST_FILE_NAME,
+};
 use log::{error, trace, LevelFilter};
 use simple_logger::SimpleLogger;
+use std::path::PathBuf;
 use std::process::exit;
 use std::sync::mpsc;
 use std::thread;
@@ -16,8 +23,6 @@ async fn main() {
         .with_level(LevelFilter::Info)
         .init()
         .unwrap();
-    println!("EMU check");
-    println!("---------");
     let opt_dir_default = dirs::data_local_dir();
     if opt_dir_default.is_none() {
         error!("Unable to determine the local data directory for the current user.");
@@ -42,20 +47,610 @@ async fn main() {
                 .required(false)
                 .default_value(opt_str_dir_default.unwrap()),
         )
+        .arg(
+            Arg::with_name("rtplan")
+                .help(
+                    "DICOM RT Plan file from which the parameters of \
+                each electron beam are imported.",
+                )
+                .long("rtplan")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("machine")
+                .help("Name of the treatment machine.")
+                .long("machine")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("applicator")
+                .help("Applicator [cm x cm], e.g. 10x10.")
+                .long("applicator")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("energy")
+                .help("Nominal energy [MeV].")
+                .long("energy")
+                .takes_value(true)
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("fda_id")
+                .help("ID of the field defining aperture.")
+                .long("fda-id")
+                .takes_value(true)
+                .validator(is_usize),
+        )
+        .arg(
+            Arg::with_name("cutout")
+                .help(
+                    "Dimensions [cm] of a rectangular cutout (e.g. 4.5x7) that replaces the FDA. \
+                    Its correction factor is derived from the FDA table.",
+                )
+                .long("cutout")
+                .takes_value(true)
+                .conflicts_with("fda_id")
+                .validator(is_cutout),
+        )
+        .arg(
+            Arg::with_name("cutout_file")
+                .help(
+                    "JSON or CSV file with the outline of an irregular cutout that replaces the FDA. \
+                    The CSV file has the x and y coordinates [cm] at isocentre of a vertex on each row.",
+                )
+                .long("cutout-file")
+                .takes_value(true)
+                .conflicts_with_all(&["fda_id", "cutout"]),
+        )
+        .arg(
+            Arg::with_name("ssd")
+                .help("Source to skin distance [cm].")
+                .long("ssd")
+                .takes_value(true)
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("dose_zref")
+                .help("Dose [cGy] at the depth of zref.")
+                .long("dose-zref")
+                .takes_value(true)
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("prescription_dose")
+                .help(
+                    "Dose [cGy] at the prescription depth, converted to the dose at zref \
+                    with the PDD table.",
+                )
+                .long("prescription-dose")
+                .takes_value(true)
+                .requires("prescription_depth")
+                .conflicts_with("dose_zref")
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("prescription_depth")
+                .help("Depth [cm] of the prescribed dose.")
+                .long("prescription-depth")
+                .takes_value(true)
+                .requires("prescription_dose")
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("incidence_angle")
+                .help(
+                    "Angle [degrees] between the beam axis and the normal to the skin, \
+                    corrected with the obliquity table.",
+                )
+                .long("incidence-angle")
+                .takes_value(true)
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("bolus_thickness")
+                .help(
+                    "Thickness [cm] of the bolus on the skin. The SSD is the SSD to the skin, \
+                    the output factor is looked up at the SSD to the bolus surface.",
+                )
+                .long("bolus-thickness")
+                .takes_value(true)
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("bolus_water_equivalence")
+                .help("Water equivalent thickness per cm of bolus [default: 1.0].")
+                .long("bolus-water-equivalence")
+                .takes_value(true)
+                .requires("bolus_thickness")
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("shield_depth")
+                .help(
+                    "Depth [cm] below the skin of the interface of an internal (lead or tungsten) \
+                    shield at or below zref, corrected with the backscatter table.",
+                )
+                .long("shield-depth")
+                .takes_value(true)
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("calc_date")
+                .help(
+                    "Date [YYYY-MM-DD] of the calculation, selects the calibration \
+                    effective on that date. Defaults to today.",
+                )
+                .long("calc-date")
+                .takes_value(true)
+                .validator(is_date),
+        )
+        .arg(
+            Arg::with_name("planned_mu")
+                .help("Planned MUs of the beam.")
+                .long("planned-mu")
+                .takes_value(true)
+                .validator(is_f64),
+        )
+        .arg(
+            Arg::with_name("no_prompt")
+                .help("Fail instead of asking for calculation parameters that are missing.")
+                .long("no-prompt"),
+        )
+        .arg(
+            Arg::with_name("predict_dose")
+                .help(
+                    "Reverse calculation: also report the dose at zref predicted from the planned \
+                    MUs and its deviation from the planned dose.",
+                )
+                .long("predict-dose"),
+        )
+        .arg(
+            Arg::with_name("require_manifest")
+                .help(
+                    "Refuse configuration data without a verified manifest, \
+                    including JSON and TOML dataset files.",
+                )
+                .long("require-manifest")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .help(
+                    "Output format of the calculation result. \
+                The json format is intended to be combined with --no-prompt or --rtplan.",
+                )
+                .long("output")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text"),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about(
+                    "Check the MUs of each beam in a CSV file and write the results to a CSV file.",
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .help(
+                            "CSV file with a header row naming the calculation parameters \
+                        (machine, applicator, energy, fda_id, ssd, dose_zref, planned_beam_mu). \
+                        A dose at another depth is given by prescription_dose and prescription_depth.",
+                        )
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .help("CSV file to which the results are written.")
+                        .index(2)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .help(
+                            "Directory containing the outputfactors and field defining apertures.",
+                        )
+                        .long("dir")
+                        .takes_value(true)
+                        .default_value(opt_str_dir_default.unwrap()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Check the outputfactors and field defining apertures in a directory and report every problem.")
+                .arg(
+                    Arg::with_name("dir")
+                        .help(
+                            "Directory containing the outputfactors and field defining apertures.",
+                        )
+                        .index(1)
+                        .required(false)
+                        .default_value(opt_str_dir_default.unwrap()),
+                )
+                .arg(
+                    Arg::with_name("reference_applicator")
+                        .help("Applicator for which the output factor is 1.0 at the reference SSD.")
+                        .long("reference-applicator")
+                        .takes_value(true)
+                        .default_value("10x10"),
+                )
+                .arg(
+                    Arg::with_name("reference_ssd")
+                        .help("Reference source to skin distance [cm].")
+                        .long("reference-ssd")
+                        .takes_value(true)
+                        .validator(is_f64)
+                        .default_value("95"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about(
+                    "Convert a directory with CSV files into a JSON or TOML dataset file or vice versa.",
+                )
+                .arg(
+                    Arg::with_name("input")
+                        .help("Directory with CSV files or a dataset file (.json or .toml).")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .help(
+                            "Dataset file (.json or .toml) if the input is a directory, \
+                        otherwise the directory to which the CSV files are written.",
+                        )
+                        .index(2)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("manifest")
+                .about(
+                    "Write a manifest with the SHA-256 hash of every CSV file in the directory \
+                to mark the current configuration data as approved.",
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .help(
+                            "Directory containing the outputfactors and field defining apertures.",
+                        )
+                        .index(1)
+                        .required(false)
+                        .default_value(opt_str_dir_default.unwrap()),
+                )
+                .arg(
+                    Arg::with_name("version")
+                        .help("Version of the configuration data.")
+                        .long("version")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("approval_date")
+                        .help("Date on which the configuration data was approved [YYYY-MM-DD].")
+                        .long("approval-date")
+                        .takes_value(true)
+                        .required(true)
+                        .validator(is_date),
+                )
+                .arg(
+                    Arg::with_name("approved_by")
+                        .help("Physicist who approved the configuration data.")
+                        .long("approved-by")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
         .get_matches();
+    let json = matches.value_of("output") == Some("json");
+    if !json {
+        println!("EMU check");
+        println!("---------");
+    }
+    if let Some(batch_matches) = matches.subcommand_matches("batch") {
+        batch(batch_matches);
+        return;
+    }
+    if let Some(convert_matches) = matches.subcommand_matches("convert") {
+        convert(convert_matches);
+        return;
+    }
+    if let Some(manifest_matches) = matches.subcommand_matches("manifest") {
+        manifest(manifest_matches);
+        return;
+    }
+    if let Some(validate_matches) = matches.subcommand_matches("validate") {
+        validate(validate_matches);
+        return;
+    }
     let dirname = matches.value_of("dir").unwrap();
     trace!("dirname: {}", dirname);
+    if matches.is_present("require_manifest") {
+        check_manifest(dirname);
+    }
+    let prompt = !matches.is_present("no_prompt");
+    let predict_dose = matches.is_present("predict_dose");
+    let dataset = DatasetIdentity::load(dirname).unwrap_or_else(|e| {
+        error!("Something went wrong:\n{}", e);
+        exit(1);
+    });
+    let tolerances = load_tolerance_table(dirname).unwrap_or_else(|e| {
+        error!("Something went wrong:\n{}", e);
+        exit(1);
+    });
 
-    let res = task::block_on(load_data_calc_mu(dirname, None));
+    if let Some(rtplan) = matches.value_of("rtplan") {
+        trace!("rtplan: {}", rtplan);
+        let res = task::block_on(load_data_compute_rt_plan(dirname, rtplan, prompt));
+        if let Err(e) = res {
+            error!("Something went wrong:\n{}", e);
+            exit(1);
+        }
+        let mut failed = false;
+        let mut reports = vec![];
+        for res_beam in res.unwrap() {
+            match res_beam {
+                Ok(computed) => {
+                    let report = CalcReport::new(dataset.clone(), computed, &tolerances);
+                    let res_report = if predict_dose {
+                        report.with_dose_prediction()
+                    } else {
+                        Ok(report)
+                    };
+                    match res_report {
+                        Ok(report) => reports.push(report),
+                        Err(e) => {
+                            error!("Something went wrong:\n{}", e);
+                            failed = true;
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!("Something went wrong:\n{}", e);
+                    failed = true;
+                }
+            }
+        }
+        if json {
+            print_json(CalcReport::list_to_json(&reports));
+        } else {
+            for report in &reports {
+                println!("{}", report);
+            }
+        }
+        if failed {
+            exit(1);
+        }
+        let verdict = reports.iter().map(|r| r.verdict).max();
+        exit(verdict.map_or(0, |v| v.exit_code()));
+    }
+
+    let mut input_params = get_calc_param_args(&matches);
+    if let Some(cutout_file) = matches.value_of("cutout_file") {
+        match read_cutout(PathBuf::from(cutout_file)) {
+            Ok(cutout) => input_params.cutout = Some(cutout),
+            Err(e) => {
+                error!("Something went wrong:\n{}", e);
+                exit(1);
+            }
+        }
+    }
+    let res = task::block_on(load_data_compute(dirname, Some(&input_params), prompt));
     if let Err(e) = res {
-        error!("Something went wrong:\n{}", e.to_string());
+        error!("Something went wrong:\n{}", e);
         exit(1);
     }
-    let (mu, calc_param) = res.unwrap();
-    let proc_diff = (1.0 - (calc_param.planned_beam_mu / mu)) * 100.0;
-    let s = format!(
-        "Calculation parameters:\n{}\nMU(check): {:.4}\nDifference[%]: {:.6}",
-        calc_param, mu, proc_diff
+    let mut report = CalcReport::new(dataset, res.unwrap(), &tolerances);
+    if predict_dose {
+        report = match report.with_dose_prediction() {
+            Ok(report) => report,
+            Err(e) => {
+                error!("Something went wrong:\n{}", e);
+                exit(1);
+            }
+        };
+    }
+    if json {
+        print_json(report.to_json());
+    } else {
+        println!("{}", report);
+    }
+    exit(report.verdict.exit_code());
+}
+
+fn batch(matches: &ArgMatches) {
+    let dirname = matches.value_of("dir").unwrap();
+    let input = matches.value_of("input").unwrap();
+    let output = matches.value_of("output").unwrap();
+    trace!("dirname: {}", dirname);
+    if matches.is_present("require_manifest") {
+        check_manifest(dirname);
+    }
+    let res = task::block_on(load_data_batch(dirname, input, output));
+    let dataset = DatasetIdentity::load(dirname).unwrap_or_else(|e| {
+        error!("Something went wrong:\n{}", e);
+        exit(1);
+    });
+    if let Err(e) = res {
+        error!("Something went wrong:\n{}", e);
+        exit(1);
+    }
+    let results = res.unwrap();
+    let count = |v| results.iter().filter(|r| r.verdict == Some(v)).count();
+    let npass = count(Verdict::Pass);
+    let nwarning = count(Verdict::Warning);
+    let nfail = count(Verdict::Fail);
+    for r in results.iter().filter(|r| !r.is_ok()) {
+        error!("Row {}: {}", r.row, r.error);
+    }
+    println!(
+        "Checked {} beams: {} passed, {} warnings, {} failed, {} errors.\nDataset: {}\nResults written to: {}",
+        results.len(),
+        npass,
+        nwarning,
+        nfail,
+        results.len() - npass - nwarning - nfail,
+        dataset,
+        output
     );
-    println!("{}", s);
+    if results.iter().any(|r| !r.is_ok()) {
+        exit(1);
+    }
+    let verdict = results.iter().filter_map(|r| r.verdict).max();
+    exit(verdict.map_or(0, |v| v.exit_code()));
+}
+
+fn validate(matches: &ArgMatches) {
+    let dirname = matches.value_of("dir").unwrap();
+    let reference = ReferenceConditions {
+        applicator: matches.value_of("reference_applicator").unwrap().to_owned(),
+        ssd: matches
+            .value_of("reference_ssd")
+            .unwrap()
+            .parse::<f64>()
+            .unwrap(),
+    };
+    trace!("dirname: {}", dirname);
+    let res = validate_data(dirname, &reference);
+    if let Err(e) = res {
+        error!("Something went wrong:\n{}", e);
+        exit(1);
+    }
+    let issues = res.unwrap();
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    if issues.is_empty() {
+        println!("No problems found in: {}", dirname);
+    } else {
+        println!("Found {} problems in: {}", issues.len(), dirname);
+        exit(1);
+    }
+}
+
+fn convert(matches: &ArgMatches) {
+    let input = matches.value_of("input").unwrap();
+    let output = matches.value_of("output").unwrap();
+    let res = if std::path::Path::new(input).is_dir() {
+        task::block_on(convert_data_to_dataset(input, output.into()))
+    } else {
+        convert_dataset_to_data(input.into(), output)
+    };
+    if let Err(e) = res {
+        error!("Something went wrong:\n{}", e);
+        exit(1);
+    }
+    println!("Converted {} to: {}", input, output);
+}
+
+fn manifest(matches: &ArgMatches) {
+    let dirname = matches.value_of("dir").unwrap();
+    let approval_date = matches
+        .value_of("approval_date")
+        .unwrap()
+        .parse::<NaiveDate>()
+        .unwrap();
+    let res = create_manifest(
+        dirname,
+        matches.value_of("version").unwrap(),
+        approval_date,
+        matches.value_of("approved_by").unwrap(),
+    )
+    .and_then(|manifest| {
+        let path_buf = std::path::Path::new(dirname).join(MANIFEST_FILE_NAME);
+        write_manifest(path_buf.clone(), &manifest)?;
+        Ok((manifest, path_buf))
+    });
+    match res {
+        Ok((manifest, path_buf)) => println!(
+            "Listed {} files of {} in: {}",
+            manifest.files.len(),
+            manifest.identity(),
+            path_buf.display()
+        ),
+        Err(e) => {
+            error!("Something went wrong:\n{}", e);
+            exit(1);
+        }
+    }
+}
+
+// Exit if the configuration data has no manifest or doesn't match it.
+fn check_manifest(dirname: &str) {
+    if let Err(e) = require_verified_manifest(dirname) {
+        error!("Something went wrong:\n{}", e);
+        exit(1);
+    }
+}
+
+fn is_date(s: String) -> Result<(), String> {
+    s.parse::<NaiveDate>()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn is_f64(s: String) -> Result<(), String> {
+    s.parse::<f64>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn is_usize(s: String) -> Result<(), String> {
+    s.parse::<usize>().map(|_| ()).map_err(|e| e.to_string())
+}
+
+fn is_cutout(s: String) -> Result<(), String> {
+    Cutout::parse_rectangle(&s)
+        .map(|_| ())
+        .ok_or_else(|| format!("Expected the dimensions of a rectangle (e.g. 4.5x7): {}", s))
+}
+
+// Calculation parameters set on the commandline, the arguments are validated by clap.
+fn get_calc_param_args(matches: &ArgMatches) -> CalcParam {
+    let mut calc_param = CalcParam::new();
+    let get_f64 = |name| matches.value_of(name).map(|s| s.parse::<f64>().unwrap());
+    if let Some(machine) = matches.value_of("machine") {
+        calc_param.machine = machine.to_owned();
+    }
+    if let Some(applicator) = matches.value_of("applicator") {
+        calc_param.applicator = applicator.to_owned();
+    }
+    if let Some(energy) = get_f64("energy") {
+        calc_param.energy = energy;
+    }
+    if let Some(fda_id) = matches.value_of("fda_id") {
+        calc_param.fda_id = fda_id.parse::<usize>().unwrap();
+    }
+    if let Some(cutout) = matches.value_of("cutout") {
+        calc_param.cutout = Cutout::parse_rectangle(cutout);
+    }
+    if let Some(ssd) = get_f64("ssd") {
+        calc_param.ssd = ssd;
+    }
+    if let Some(dose_zref) = get_f64("dose_zref") {
+        calc_param.dose_zref = dose_zref;
+    }
+    calc_param.prescription_dose = get_f64("prescription_dose");
+    calc_param.prescription_depth = get_f64("prescription_depth");
+    calc_param.incidence_angle = get_f64("incidence_angle");
+    calc_param.bolus_thickness = get_f64("bolus_thickness");
+    calc_param.bolus_water_equivalence = get_f64("bolus_water_equivalence");
+    calc_param.shield_depth = get_f64("shield_depth");
+    calc_param.calc_date = matches
+        .value_of("calc_date")
+        .map(|s| s.parse::<NaiveDate>().unwrap());
+    if let Some(planned_beam_mu) = get_f64("planned_mu") {
+        calc_param.planned_beam_mu = planned_beam_mu;
+    }
+    calc_param
+}
+
+fn print_json(res: Result<String, EmuError>) {
+    match res {
+        Ok(s) => println!("{}", s),
+        Err(e) => {
+            error!("Something went wrong:\n{}", e);
+            exit(1);
+        }
+    }
 }
@@ -0,0 +1,241 @@
+use crate::{EmuError, CALC_OPTIONS_FILE_NAME, CSV_FORMAT_FILE_NAME};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the manifest file in the directory with the configuration data.
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Approved version of the configuration data.
+/// Lists the SHA-256 hash of every CSV file (and the CSV format and calculation options files)
+/// in the directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: String,
+    pub approval_date: NaiveDate,
+    pub approved_by: String,             // physicist who approved the data
+    pub files: BTreeMap<String, String>, // file name -> SHA-256 hash (hexadecimal)
+}
+
+/// Identity of an approved version of the configuration data.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestIdentity {
+    pub version: String,
+    pub approval_date: NaiveDate,
+    pub approved_by: String,
+}
+
+impl Manifest {
+    pub fn identity(&self) -> ManifestIdentity {
+        ManifestIdentity {
+            version: self.version.clone(),
+            approval_date: self.approval_date,
+            approved_by: self.approved_by.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for ManifestIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "version {} approved by {} on {}",
+            self.version, self.approved_by, self.approval_date
+        )
+    }
+}
+
+/// Compute the SHA-256 hash of a file as a hexadecimal string.
+pub fn sha256_file(path_buf: PathBuf) -> Result<String, EmuError> {
+    let bytes =
+        std::fs::read(&path_buf).map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    Ok(format!("{:x}", Sha256::digest(&bytes)))
+}
+
+// Files in the directory that must be listed in the manifest.
+fn list_manifest_files(dirname: &str) -> Result<BTreeMap<String, PathBuf>, EmuError> {
+    let dir = PathBuf::from(dirname);
+    if !dir.is_dir() {
+        return Err(EmuError::DirNotFound(dir));
+    }
+    let mut files = BTreeMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let ep = entry?.path();
+        if ep.is_dir() {
+            continue;
+        }
+        let file_name = ep
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("")
+            .to_owned();
+        if file_name.ends_with(".csv")
+            || file_name == CSV_FORMAT_FILE_NAME
+            || file_name == CALC_OPTIONS_FILE_NAME
+        {
+            files.insert(file_name, ep);
+        }
+    }
+    Ok(files)
+}
+
+/// Create a manifest with the hashes of the files currently in the directory.
+pub fn create_manifest(
+    dirname: &str,
+    version: &str,
+    approval_date: NaiveDate,
+    approved_by: &str,
+) -> Result<Manifest, EmuError> {
+    let mut files = BTreeMap::new();
+    for (file_name, path_buf) in list_manifest_files(dirname)? {
+        files.insert(file_name, sha256_file(path_buf)?);
+    }
+    Ok(Manifest {
+        version: version.to_owned(),
+        approval_date,
+        approved_by: approved_by.to_owned(),
+        files,
+    })
+}
+
+pub fn read_manifest(path_buf: PathBuf) -> Result<Manifest, EmuError> {
+    let content = std::fs::read_to_string(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    serde_json::from_str(&content).map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))
+}
+
+pub fn write_manifest(path_buf: PathBuf, manifest: &Manifest) -> Result<(), EmuError> {
+    let content = serde_json::to_string_pretty(manifest)
+        .map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))?;
+    std::fs::write(&path_buf, content).map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))
+}
+
+/// Load the manifest of the configuration data, None if the directory has no manifest.
+pub fn load_manifest(dirname: &str) -> Result<Option<Manifest>, EmuError> {
+    let path_buf = Path::new(dirname).join(MANIFEST_FILE_NAME);
+    if path_buf.is_file() {
+        Ok(Some(read_manifest(path_buf)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Check that the files in the directory are exactly the files listed in the manifest
+/// and that their hashes match. Every mismatch is reported in the error.
+pub fn verify_manifest(dirname: &str, manifest: &Manifest) -> Result<(), EmuError> {
+    let files = list_manifest_files(dirname)?;
+    let mut mismatches = vec![];
+    for (file_name, path_buf) in &files {
+        match manifest.files.get(file_name) {
+            None => mismatches.push(format!("{} is not listed", file_name)),
+            Some(hash) => {
+                if !sha256_file(path_buf.clone())?.eq_ignore_ascii_case(hash) {
+                    mismatches.push(format!("{} was modified", file_name));
+                }
+            }
+        }
+    }
+    for file_name in manifest.files.keys() {
+        if !files.contains_key(file_name) {
+            mismatches.push(format!("{} is missing", file_name));
+        }
+    }
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(EmuError::ManifestMismatch(
+            manifest.version.clone(),
+            mismatches,
+        ))
+    }
+}
+
+/// Load and verify the manifest of the configuration data.
+pub fn load_verified_manifest(dirname: &str) -> Result<Option<Manifest>, EmuError> {
+    let opt_manifest = load_manifest(dirname)?;
+    if let Some(manifest) = &opt_manifest {
+        verify_manifest(dirname, manifest)?;
+    }
+    Ok(opt_manifest)
+}
+
+/// Load and verify the manifest of the configuration data, the data must have a manifest.
+/// A JSON or TOML dataset file has no manifest and is refused.
+pub fn require_verified_manifest(dirname: &str) -> Result<Manifest, EmuError> {
+    if !Path::new(dirname).is_dir() {
+        return Err(EmuError::ManifestNotFound(PathBuf::from(dirname)));
+    }
+    load_verified_manifest(dirname)?
+        .ok_or_else(|| EmuError::ManifestNotFound(PathBuf::from(dirname)))
+}
+
+/// SHA-256 hash of the manifest of the configuration data, None if there is no manifest.
+pub fn manifest_hash(dirname: &str) -> Result<Option<String>, EmuError> {
+    let path_buf = Path::new(dirname).join(MANIFEST_FILE_NAME);
+    if path_buf.is_file() {
+        Ok(Some(sha256_file(path_buf)?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn manifest_verify() {
+        let tmp =
+            std::env::temp_dir().join(format!("emu_check_manifest_verify_{}", std::process::id()));
+        std::fs::create_dir_all(&tmp).unwrap();
+        for file_name in &["of_app_10x10.csv", "fda_app_10x10.csv"] {
+            std::fs::copy(Path::new("resources").join(file_name), tmp.join(file_name)).unwrap();
+        }
+        let dirname = tmp.to_str().unwrap();
+        assert!(load_verified_manifest(dirname).unwrap().is_none());
+        assert!(matches!(
+            require_verified_manifest(dirname),
+            Err(EmuError::ManifestNotFound(_))
+        ));
+        assert!(manifest_hash(dirname).unwrap().is_none());
+
+        let date = NaiveDate::from_ymd_opt(2020, 7, 1).unwrap();
+        let manifest = create_manifest(dirname, "1.0", date, "Physicist").unwrap();
+        assert_eq!(manifest.files.len(), 2);
+        write_manifest(tmp.join(MANIFEST_FILE_NAME), &manifest).unwrap();
+        let verified = load_verified_manifest(dirname).unwrap().unwrap();
+        assert_eq!(verified.identity(), manifest.identity());
+        let verified = require_verified_manifest(dirname).unwrap();
+        assert_eq!(verified.identity(), manifest.identity());
+        assert_eq!(
+            manifest_hash(dirname).unwrap(),
+            Some(sha256_file(tmp.join(MANIFEST_FILE_NAME)).unwrap())
+        );
+        // a dataset file has no manifest
+        assert!(matches!(
+            require_verified_manifest(tmp.join("of_app_10x10.csv").to_str().unwrap()),
+            Err(EmuError::ManifestNotFound(_))
+        ));
+
+        std::fs::write(tmp.join("of_app_10x10.csv"), "modified").unwrap();
+        std::fs::remove_file(tmp.join("fda_app_10x10.csv")).unwrap();
+        std::fs::write(tmp.join("tolerances.csv"), "").unwrap();
+        match load_verified_manifest(dirname) {
+            Err(EmuError::ManifestMismatch(version, mismatches)) => {
+                assert_eq!(version, "1.0");
+                assert_eq!(
+                    mismatches,
+                    vec![
+                        "of_app_10x10.csv was modified",
+                        "tolerances.csv is not listed",
+                        "fda_app_10x10.csv is missing"
+                    ]
+                );
+            }
+            _ => panic!("Expected a manifest mismatch"),
+        }
+        std::fs::remove_dir_all(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+use crate::energy_table::{EnergyTable, TableKind};
+use crate::errors::EmuError;
+use crate::{CalcParam, CorrectionData};
+
+/// Prefix of the files with the obliquity correction factors.
+pub const OBLIQUITY_PREFIX: &str = "obl_";
+
+/// Marker of the obliquity correction factor per energy as a function of the angle of
+/// incidence [degrees]: the dose at zref at oblique incidence relative to the dose at
+/// perpendicular incidence.
+#[derive(Debug, Clone)]
+pub struct Obliquity;
+
+impl TableKind for Obliquity {
+    const PREFIX: &'static str = OBLIQUITY_PREFIX;
+    const LABEL: &'static str = "Angle";
+    const VARIABLE: &'static str = "angle [degrees]";
+    const FACTOR: &'static str = "obliquity factor";
+
+    fn out_of_range(angle: f64) -> EmuError {
+        EmuError::AngleNotFound(angle)
+    }
+
+    fn table(cd: &CorrectionData) -> Option<&ObliquityTable> {
+        cd.obliquity.as_ref()
+    }
+
+    fn set_table(cd: &mut CorrectionData, table: ObliquityTable) {
+        cd.obliquity = Some(table);
+    }
+}
+
+/// Obliquity correction factor per energy as a function of the angle of incidence [degrees].
+pub type ObliquityTable = EnergyTable<Obliquity>;
+
+impl CorrectionData {
+    /// Get the obliquity correction factor for the angle of incidence.
+    /// None if the beam is perpendicular to the skin and there is no obliquity table.
+    pub fn get_obliquity_factor(&self, calc_param: &CalcParam) -> Result<Option<f64>, EmuError> {
+        let angle = match calc_param.incidence_angle {
+            Some(angle) => angle,
+            None => return Ok(None),
+        };
+        if !(0.0..90.0).contains(&angle) {
+            return Err(EmuError::InvalidInput(
+                "incidence_angle".to_owned(),
+                angle.to_string(),
+            ));
+        }
+        match &self.obliquity {
+            Some(obliquity) => Ok(Some(obliquity.get_cf(
+                calc_param.energy,
+                angle,
+                self.options.energy_interpolation,
+            )?)),
+            None if angle == 0.0 => Ok(None),
+            None => Err(EmuError::ObliquityTableNotFound(
+                self.machine.clone(),
+                self.applicator.clone(),
+            )),
+        }
+    }
+}
@@ -1,5 +1,9 @@
+use crate::csv_format::{
+    check_energies, csv_reader, expect_label, format_number, label_row, parse_cell, parse_cells,
+    record_row, write_rows, CsvFormat,
+};
 use crate::errors::EmuError;
-use crate::ipol::interpolate_linear;
+use crate::ipol::{check_abscissae, energy_column, InterpolationMethod};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -12,6 +16,55 @@ pub struct OFTable {
                                      // in the table [each column contains the output factors for one energy].
 }
 
+/// Neighbouring tabulated energies between which the correction factors were interpolated
+/// for an energy that isn't tabulated.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EnergyInterpolation {
+    pub energy0: f64,
+    pub energy1: f64,
+}
+
+/// Output factor correction interpolated between the neighbouring tabulated SSDs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OFInterpolation {
+    pub ssd0: f64,
+    pub ssd1: f64,
+    pub of0: f64,
+    pub of1: f64,
+    pub cf: f64,
+    pub energy_ipol: Option<EnergyInterpolation>, // None if the energy is tabulated
+    pub method: Option<InterpolationMethod>,      // None if the virtual source model was used
+    pub virtual_source: Option<VirtualSourceFit>, // Some if the virtual source model was used
+}
+
+/// Virtual (effective) source model fitted to the output factors of an energy:
+/// OF(SSD) = of_ref * ((virtual_ssd + zref) / (virtual_ssd + SSD - ssd_ref + zref))^2
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VirtualSourceFit {
+    pub ssd_ref: f64,     // smallest tabulated SSD [cm]
+    pub virtual_ssd: f64, // distance [cm] from the virtual source to the surface at ssd_ref
+    pub zref: f64,
+    pub of_ref: f64,                // output factor of the model at ssd_ref
+    pub residuals: Vec<(f64, f64)>, // (SSD, tabulated - model output factor)
+    pub extrapolated: bool,         // true if the SSD is outside the tabulated SSDs
+}
+
+impl VirtualSourceFit {
+    /// Output factor of the model at an SSD [cm].
+    pub fn output_factor(&self, ssd: f64) -> f64 {
+        let r =
+            (self.virtual_ssd + self.zref) / (self.virtual_ssd + ssd - self.ssd_ref + self.zref);
+        self.of_ref * r * r
+    }
+
+    /// Largest absolute fit residual.
+    pub fn max_residual(&self) -> f64 {
+        self.residuals
+            .iter()
+            .fold(0.0, |m: f64, (_, r)| m.max(r.abs()))
+    }
+}
+
 impl OFTable {
     pub fn new() -> Self {
         OFTable {
@@ -74,26 +127,41 @@ impl OFTable {
 
     // Get the output factor correction based on the energy and the source to skin distance [SSD].
     pub fn get_cf(&self, energy: f64, ssd: f64) -> Result<f64, EmuError> {
-        // find matching energy
-        let mut energy_idx = self.energies.len();
-        for idx in 0..self.energies.len() {
-            if (energy - *self.energies.get(idx).unwrap()).abs() < std::f64::EPSILON {
-                energy_idx = idx;
-                break;
-            }
-        }
-        if self.energies.len() == energy_idx {
-            return Err(EmuError::EnergyNotFound(energy));
-        }
+        Ok(self.get_cf_ipol(energy, ssd)?.cf)
+    }
 
-        // Found a matching energy, interpolate output factor by SSD
-        let opt_ofs = self.table.get(energy_idx);
-        if opt_ofs.is_none() {
-            return Err(EmuError::Logic(
-                "Energy matching outputfactor column in table was not found.".to_string(),
-            ));
-        }
-        let ofs = opt_ofs.unwrap();
+    // Get the output factor correction together with the tabulated SSDs it's interpolated from.
+    pub fn get_cf_ipol(&self, energy: f64, ssd: f64) -> Result<OFInterpolation, EmuError> {
+        self.interpolate(energy, ssd, false, InterpolationMethod::Linear)
+    }
+
+    // Get the output factor correction, interpolated between the neighbouring tabulated energies
+    // if the energy isn't tabulated.
+    pub fn get_cf_ipol_energy(&self, energy: f64, ssd: f64) -> Result<OFInterpolation, EmuError> {
+        self.interpolate(energy, ssd, true, InterpolationMethod::Linear)
+    }
+
+    /// Get the output factor correction interpolated between the SSDs with the given method.
+    pub fn get_cf_ipol_with(
+        &self,
+        energy: f64,
+        ssd: f64,
+        energy_interpolation: bool,
+        method: InterpolationMethod,
+    ) -> Result<OFInterpolation, EmuError> {
+        self.interpolate(energy, ssd, energy_interpolation, method)
+    }
+
+    fn interpolate(
+        &self,
+        energy: f64,
+        ssd: f64,
+        energy_interpolation: bool,
+        method: InterpolationMethod,
+    ) -> Result<OFInterpolation, EmuError> {
+        check_abscissae("SSDs", &self.ssds)?;
+        let (ofs, energy_ipol) =
+            energy_column(&self.energies, &self.table, energy, energy_interpolation)?;
         let n = ofs.len();
         if n != self.ssds.len() {
             return Err(EmuError::Logic(
@@ -102,12 +170,12 @@ impl OFTable {
         }
 
         // look for the closest SSD match
-        let mut x0 = std::f64::MIN;
-        let mut x1 = std::f64::MAX;
-        let mut y0 = std::f64::MAX;
-        let mut y1 = std::f64::MAX;
-        let mut dx0 = std::f64::MAX;
-        let mut dx1 = std::f64::MAX;
+        let mut x0 = f64::MIN;
+        let mut x1 = f64::MAX;
+        let mut y0 = f64::MAX;
+        let mut y1 = f64::MAX;
+        let mut dx0 = f64::MAX;
+        let mut dx1 = f64::MAX;
         for i in 0..n {
             let issd = self.ssds.get(i).unwrap();
             let dx = (*issd - ssd).abs();
@@ -122,13 +190,118 @@ impl OFTable {
                 dx1 = dx;
             }
         }
-        if x0 == std::f64::MIN {
+        if x0 == f64::MIN {
             return Err(EmuError::SSDNotFound(ssd));
         }
-        if x1 == std::f64::MAX {
+        if x1 == f64::MAX {
+            return Err(EmuError::SSDNotFound(ssd));
+        }
+        Ok(OFInterpolation {
+            ssd0: x0,
+            ssd1: x1,
+            of0: y0,
+            of1: y1,
+            cf: method
+                .interpolator()
+                .interpolate(&self.ssds, &ofs, ssd)
+                .ok_or(EmuError::SSDNotFound(ssd))?,
+            energy_ipol,
+            method: Some(method),
+            virtual_source: None,
+        })
+    }
+
+    /// Fit the virtual source model to the output factors of an energy.
+    /// 1/sqrt(OF) is linear in the SSD, the line is fitted by least squares.
+    pub fn fit_virtual_source(
+        &self,
+        energy: f64,
+        zref: f64,
+        energy_interpolation: bool,
+    ) -> Result<VirtualSourceFit, EmuError> {
+        let (ofs, _) = energy_column(&self.energies, &self.table, energy, energy_interpolation)?;
+        if ofs.len() != self.ssds.len() {
+            return Err(EmuError::Logic(
+                "Number of SSDs differs from the number of outputfactors.".to_string(),
+            ));
+        }
+        if self.ssds.len() < 2 || ofs.iter().any(|of| of.is_nan() || *of <= 0.0) {
+            return Err(EmuError::Logic(format!(
+                "The virtual source model for energy [{}] requires at least two SSDs \
+                with a positive output factor",
+                energy
+            )));
+        }
+        let n = self.ssds.len() as f64;
+        let ys: Vec<f64> = ofs.iter().map(|of| 1.0 / of.sqrt()).collect();
+        let mx = self.ssds.iter().sum::<f64>() / n;
+        let my = ys.iter().sum::<f64>() / n;
+        let sxy: f64 = self
+            .ssds
+            .iter()
+            .zip(ys.iter())
+            .map(|(x, y)| (x - mx) * (y - my))
+            .sum();
+        let sxx: f64 = self.ssds.iter().map(|x| (x - mx) * (x - mx)).sum();
+        let b = sxy / sxx;
+        let a = my - b * mx;
+        if !b.is_finite() || b <= 0.0 {
+            return Err(EmuError::Logic(format!(
+                "The output factors of energy [{}] don't decrease with the SSD, \
+                the virtual source model can't be fitted",
+                energy
+            )));
+        }
+        let ssd_ref = self.ssds.iter().cloned().fold(f64::MAX, f64::min);
+        // 1/sqrt(OF) = b * (SSD + a/b), with SSD + a/b the distance from the virtual source to zref
+        let y_ref = a + b * ssd_ref;
+        let mut fit = VirtualSourceFit {
+            ssd_ref,
+            virtual_ssd: y_ref / b - zref,
+            zref,
+            of_ref: 1.0 / (y_ref * y_ref),
+            residuals: vec![],
+            extrapolated: false,
+        };
+        fit.residuals = self
+            .ssds
+            .iter()
+            .zip(ofs.iter())
+            .map(|(ssd, of)| (*ssd, of - fit.output_factor(*ssd)))
+            .collect();
+        Ok(fit)
+    }
+
+    /// Get the output factor correction from the virtual source model. SSDs up to
+    /// `extrapolation` [cm] outside the tabulated SSDs are accepted.
+    pub fn get_cf_virtual_source(
+        &self,
+        energy: f64,
+        ssd: f64,
+        zref: f64,
+        energy_interpolation: bool,
+        extrapolation: f64,
+    ) -> Result<OFInterpolation, EmuError> {
+        let (_, energy_ipol) =
+            energy_column(&self.energies, &self.table, energy, energy_interpolation)?;
+        let mut fit = self.fit_virtual_source(energy, zref, energy_interpolation)?;
+        let min = self.ssds.iter().cloned().fold(f64::MAX, f64::min);
+        let max = self.ssds.iter().cloned().fold(f64::MIN, f64::max);
+        if ssd < min - extrapolation || ssd > max + extrapolation {
             return Err(EmuError::SSDNotFound(ssd));
         }
-        Ok(interpolate_linear(ssd, x0, x1, y0, y1))
+        fit.extrapolated = ssd < min || ssd > max;
+        let cf = fit.output_factor(ssd);
+        Ok(OFInterpolation {
+            ssd0: ssd,
+            ssd1: ssd,
+            of0: cf,
+            of1: cf,
+            cf,
+            energy_ipol,
+            method: None,
+            virtual_source: Some(fit),
+        })
     }
 }
 
@@ -139,21 +312,24 @@ impl Default for OFTable {
 }
 
 pub fn read_of_table(path_buf: PathBuf) -> Result<(String, String, OFTable), EmuError> {
+    read_of_table_with_format(path_buf, None)
+}
+
+/// Read the table using the given CSV format, or the format detected from the content if None.
+pub fn read_of_table_with_format(
+    path_buf: PathBuf,
+    opt_format: Option<CsvFormat>,
+) -> Result<(String, String, OFTable), EmuError> {
     let mut of_table = OFTable::new();
     let mut machine = "".to_owned();
     let mut applicator = "".to_owned();
-    let res_rdr = csv::ReaderBuilder::new()
-        .has_headers(false)
-        .from_path(path_buf.clone());
-    if let Err(e) = res_rdr {
-        return Err(EmuError::IO(format!("{:#?}: {}", path_buf, e.to_string())));
-    }
-    let mut rdr = res_rdr.unwrap();
+    let (mut rdr, format) = csv_reader(&path_buf, opt_format)?;
+    let path = path_buf.as_path();
     let mut nc = 0;
     let mut i = 0;
     for record in rdr.records() {
         if let Err(e) = record {
-            return Err(EmuError::IO(format!("{:#?}: {}", path_buf, e.to_string())));
+            return Err(EmuError::IO(format!("{:#?}: {}", path_buf, e)));
         }
         let record = record.unwrap();
         let nrecord = record.len();
@@ -165,66 +341,27 @@ pub fn read_of_table(path_buf: PathBuf) -> Result<(String, String, OFTable), Emu
         }
         if nc != nrecord {
             return Err(EmuError::Format(format!(
-                "All rows in the CSV file must have the same number of columns [{} <-> {}]",
-                nc, nrecord
+                "{:#?} row {}: All rows in the CSV file must have the same number of columns [{} <-> {}]",
+                path_buf,
+                record_row(&record),
+                nc,
+                nrecord
             )));
         }
         if i == 0 {
             machine = record[0].to_string();
         } else if i == 1 {
-            if &record[0] != "Applicator" {
-                return Err(EmuError::Format(
-                    "Expected the label \'Applicator\' on row 1, column 0".to_owned(),
-                ));
-            }
+            expect_label(path, &record, 0, "Applicator")?;
             applicator = record[1].to_string();
         } else if i == 2 {
-            if &record[0] != "Energy" {
-                return Err(EmuError::Format(
-                    "Expected the label \'Energy\' on row 2, column 0".to_owned(),
-                ));
-            }
-            let mut energies = Vec::with_capacity(nrecord - 1);
-            for j in 1..nrecord {
-                let s = &record[j];
-                let res_f = s.parse::<f64>();
-                if let Err(e) = res_f {
-                    return Err(EmuError::Format(e.to_string()));
-                }
-                energies.push(res_f.unwrap());
-            }
-            of_table.energies = energies;
+            expect_label(path, &record, 0, "Energy")?;
+            of_table.energies = parse_cells(path, &record, 1, "a number (energy [MeV])", &format)?;
         } else if i == 3 {
-            if &record[0] != "SSD/Zref" {
-                return Err(EmuError::Format(
-                    "Expected the label \'SSD/Zref\' on row 3, column 0".to_owned(),
-                ));
-            }
-            let mut zrefs = Vec::with_capacity(nrecord - 1);
-            for j in 1..nrecord {
-                let s = &record[j];
-                let res_f = s.parse::<f64>();
-                if let Err(e) = res_f {
-                    return Err(EmuError::Format(e.to_string()));
-                }
-                zrefs.push(res_f.unwrap());
-            }
-            of_table.zrefs = zrefs;
+            expect_label(path, &record, 0, "SSD/Zref")?;
+            of_table.zrefs = parse_cells(path, &record, 1, "a number (zref [cm])", &format)?;
         } else {
-            let ssd = (&record[0]).parse::<f64>();
-            if let Err(e) = ssd {
-                return Err(EmuError::Format(e.to_string()));
-            }
-            let ssd = ssd.unwrap();
-            let mut vof = Vec::with_capacity(nrecord - 1);
-            for j in 1..nrecord {
-                let s = &record[j];
-                let res_f = s.parse::<f64>();
-                if let Err(e) = res_f {
-                    return Err(EmuError::Format(e.to_string()));
-                }
-                vof.push(res_f.unwrap());
-            }
+            let ssd = parse_cell(path, &record, 0, "a number (SSD [cm])", &format)?;
+            let vof = parse_cells(path, &record, 1, "a number (output factor)", &format)?;
             of_table.add_output_factor_per_ssd(ssd, vof)?;
         }
         i += 1;
@@ -232,6 +369,50 @@ pub fn read_of_table(path_buf: PathBuf) -> Result<(String, String, OFTable), Emu
     Ok((machine, applicator, of_table))
 }
 
+/// Write the table, together with its machine and applicator, in the layout read by read_of_table.
+pub fn write_of_table(
+    path_buf: PathBuf,
+    machine: &str,
+    applicator: &str,
+    of_table: &OFTable,
+) -> Result<(), EmuError> {
+    write_of_table_with_format(
+        path_buf,
+        machine,
+        applicator,
+        of_table,
+        &CsvFormat::default(),
+    )
+}
+
+/// Write the table using the delimiter and decimal separator of the CSV format.
+pub fn write_of_table_with_format(
+    path_buf: PathBuf,
+    machine: &str,
+    applicator: &str,
+    of_table: &OFTable,
+    format: &CsvFormat,
+) -> Result<(), EmuError> {
+    check_energies(&path_buf, &of_table.energies)?;
+    let nc = of_table.energies.len() + 1;
+    let values = |label: String, v: &[f64]| {
+        let mut row = vec![label];
+        row.extend(v.iter().map(|x| format_number(*x, format)));
+        row
+    };
+    let mut rows = vec![
+        label_row(machine, "", nc),
+        label_row("Applicator", applicator, nc),
+        values("Energy".to_owned(), &of_table.energies),
+        values("SSD/Zref".to_owned(), &of_table.zrefs),
+    ];
+    for (i, ssd) in of_table.ssds.iter().enumerate() {
+        let ofs: Vec<f64> = of_table.table.iter().map(|col| col[i]).collect();
+        rows.push(values(format_number(*ssd, format), &ofs));
+    }
+    write_rows(&path_buf, &rows, format)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -274,6 +455,35 @@ mod test {
     }
 
     #[test]
+    fn of_table_virtual_source() {
+        let mut of_table = OFTable::new();
+        of_table.set_energies(vec![6.0]);
+        of_table.set_zrefs(vec![1.36]);
+        // inverse square from a virtual source at 80 cm from the surface at SSD 95
+        let of = |ssd: f64| ((80.0_f64 + 1.36) / (80.0 + ssd - 95.0 + 1.36)).powi(2);
+        for ssd in &[95.0, 100.0, 105.0, 110.0, 115.0] {
+            of_table
+                .add_output_factor_per_ssd(*ssd, vec![of(*ssd)])
+                .unwrap();
+        }
+        let fit = of_table.fit_virtual_source(6.0, 1.36, false).unwrap();
+        assert!((fit.virtual_ssd - 80.0).abs() < 1e-9);
+        assert!((fit.of_ref - 1.0).abs() < 1e-12);
+        assert!(fit.max_residual() < 1e-12);
+
+        let ipol = of_table
+            .get_cf_virtual_source(6.0, 120.0, 1.36, false, 5.0)
+            .unwrap();
+        assert!((ipol.cf - of(120.0)).abs() < 1e-12);
+        assert!(ipol.virtual_source.unwrap().extrapolated);
+        assert!(matches!(
+            of_table.get_cf_virtual_source(6.0, 120.5, 1.36, false, 5.0),
+            Err(EmuError::SSDNotFound(_))
+        ));
+    }
+
+    #[test]
+    #[allow(clippy::get_first)]
     fn test_build() {
         let of_table = build_of_table();
         assert_eq!(*of_table.energies.get(0).unwrap(), 4.0);
@@ -283,6 +493,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::float_equality_without_abs, clippy::legacy_numeric_constants)]
     fn test_get_cf() {
         let of_table = build_of_table();
         assert_eq!(of_table.get_cf(4.0, 97.0).unwrap(), 0.818);
@@ -299,4 +510,86 @@ mod test {
         assert!(of_table.get_cf(8.0, 94.9).is_err());
         assert!(of_table.get_cf(8.0, 115.1).is_err());
     }
+
+    #[test]
+    fn test_get_cf_ipol() {
+        let of_table = build_of_table();
+        let ipol = of_table.get_cf_ipol(4.0, 97.3).unwrap();
+        assert_eq!(ipol.ssd0, 97.0);
+        assert_eq!(ipol.ssd1, 98.0);
+        assert_eq!(ipol.of0, 0.818);
+        assert_eq!(ipol.of1, 0.792);
+        assert_eq!(ipol.cf, 0.8102);
+    }
+
+    #[test]
+    fn read_of_table_decimal_comma() {
+        let path_buf = PathBuf::from("resources/of_app_10x10.csv");
+        let content = std::fs::read_to_string(&path_buf).unwrap();
+        let content = content.replace(',', ";").replace('.', ",");
+        let dir =
+            std::env::temp_dir().join(format!("emu_check_of_decimal_comma_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tmp = dir.join("of.csv");
+        std::fs::write(&tmp, content).unwrap();
+        let (machine, applicator, table) = read_of_table(tmp.clone()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (_, _, expected) = read_of_table(path_buf).unwrap();
+        assert_eq!(machine, "Synergy2");
+        assert_eq!(applicator, "10x10");
+        assert_eq!(table.zrefs, expected.zrefs);
+        assert_eq!(table.ssds, expected.ssds);
+        assert_eq!(table.table, expected.table);
+    }
+
+    #[test]
+    fn write_of_table_round_trip() {
+        let of_table = build_of_table();
+        let formats = [
+            CsvFormat::default(),
+            CsvFormat {
+                delimiter: ';',
+                decimal_separator: ',',
+            },
+        ];
+        let dir =
+            std::env::temp_dir().join(format!("emu_check_of_round_trip_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, format) in formats.iter().enumerate() {
+            let tmp = dir.join(format!("of_{}.csv", i));
+            write_of_table_with_format(tmp.clone(), "Synergy2", "6x6", &of_table, format).unwrap();
+            let (machine, applicator, table) = read_of_table(tmp.clone()).unwrap();
+            std::fs::remove_file(&tmp).unwrap();
+            assert_eq!(machine, "Synergy2");
+            assert_eq!(applicator, "6x6");
+            assert_eq!(table.energies, of_table.energies);
+            assert_eq!(table.zrefs, of_table.zrefs);
+            assert_eq!(table.ssds, of_table.ssds);
+            assert_eq!(table.table, of_table.table);
+        }
+
+        let tmp = dir.join("of_empty.csv");
+        assert!(write_of_table(tmp.clone(), "Synergy2", "6x6", &OFTable::new()).is_err());
+        assert!(!tmp.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_get_cf_ipol_energy() {
+        let of_table = build_of_table();
+        // tabulated energies aren't marked as interpolated
+        let ipol = of_table.get_cf_ipol_energy(4.0, 97.3).unwrap();
+        assert_eq!(ipol.cf, 0.8102);
+        assert!(ipol.energy_ipol.is_none());
+
+        let ipol = of_table.get_cf_ipol_energy(5.0, 97.0).unwrap();
+        assert!((ipol.cf - 0.86).abs() < 1e-12);
+        let energy_ipol = ipol.energy_ipol.unwrap();
+        assert_eq!(energy_ipol.energy0, 4.0);
+        assert_eq!(energy_ipol.energy1, 6.0);
+
+        assert!(of_table.get_cf_ipol(5.0, 97.0).is_err());
+        assert!(of_table.get_cf_ipol_energy(3.0, 97.0).is_err());
+        assert!(of_table.get_cf_ipol_energy(13.0, 97.0).is_err());
+    }
 }
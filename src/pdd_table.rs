@@ -0,0 +1,83 @@
+use crate::energy_table::{EnergyTable, TableKind};
+use crate::errors::EmuError;
+use crate::{CalcParam, CorrectionData};
+use serde::{Deserialize, Serialize};
+
+/// Prefix of the files with the percentage depth doses.
+pub const PDD_PREFIX: &str = "pdd_";
+
+/// Marker of the percentage depth dose [%] per energy as a function of the depth [cm].
+#[derive(Debug, Clone)]
+pub struct Pdd;
+
+impl TableKind for Pdd {
+    const PREFIX: &'static str = PDD_PREFIX;
+    const LABEL: &'static str = "Depth";
+    const VARIABLE: &'static str = "depth [cm]";
+    const FACTOR: &'static str = "PDD [%]";
+
+    fn out_of_range(depth: f64) -> EmuError {
+        EmuError::DepthNotFound(depth)
+    }
+
+    fn table(cd: &CorrectionData) -> Option<&PddTable> {
+        cd.pdd.as_ref()
+    }
+
+    fn set_table(cd: &mut CorrectionData, table: PddTable) {
+        cd.pdd = Some(table);
+    }
+}
+
+/// Percentage depth dose [%] per energy as a function of the depth [cm].
+pub type PddTable = EnergyTable<Pdd>;
+
+/// Conversion of a dose prescribed at a depth other than zref to the dose at zref.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PddConversion {
+    pub pdd_depth: f64, // PDD [%] at the prescription depth
+    pub pdd_zref: f64,  // PDD [%] at zref
+}
+
+impl CorrectionData {
+    /// Convert the dose prescribed at a depth to the dose at zref:
+    /// D(zref) = D(depth) * PDD(zref) / PDD(depth).
+    /// The prescription depth is below the skin, with bolus the PDD is looked up at the depth
+    /// below the bolus surface.
+    /// Returns the calculation parameters with dose_zref set and the PDDs used,
+    /// or the unchanged parameters if no prescription is set.
+    pub fn resolve_prescription(
+        &self,
+        calc_param: &CalcParam,
+    ) -> Result<(CalcParam, Option<PddConversion>), EmuError> {
+        let (dose, depth) = match (calc_param.prescription_dose, calc_param.prescription_depth) {
+            (Some(dose), Some(depth)) => (dose, depth),
+            _ => return Ok((calc_param.clone(), None)),
+        };
+        let pdd = self.pdd.as_ref().ok_or_else(|| {
+            EmuError::PddTableNotFound(self.machine.clone(), self.applicator.clone())
+        })?;
+        let mut cp = calc_param.clone();
+        if !cp.has_depth_zref() {
+            cp.depth_zref = self
+                .get_zref(cp.energy)
+                .ok_or(EmuError::EnergyNotFound(cp.energy))?;
+        }
+        let depth = depth
+            + cp.bolus_adjustment()?
+                .map_or(0.0, |b| b.water_equivalent_thickness());
+        let energy_interpolation = self.options.energy_interpolation;
+        let conversion = PddConversion {
+            pdd_depth: pdd.get_cf(cp.energy, depth, energy_interpolation)?,
+            pdd_zref: pdd.get_cf(cp.energy, cp.depth_zref, energy_interpolation)?,
+        };
+        if conversion.pdd_depth <= 0.0 {
+            return Err(EmuError::InvalidInput(
+                "prescription_depth".to_owned(),
+                format!("{} (PDD is {}%)", depth, conversion.pdd_depth),
+            ));
+        }
+        cp.dose_zref = dose * conversion.pdd_zref / conversion.pdd_depth;
+        Ok((cp, Some(conversion)))
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    load_manifest, Computed, DosePrediction, EmuError, InterpolationMethod, ManifestIdentity,
+    Tolerance, ToleranceTable, Verdict,
+};
+use serde::{Deserialize, Serialize};
+
+/// Identifies the configuration data used in a calculation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatasetIdentity {
+    pub dir: String,
+    pub manifest: Option<ManifestIdentity>,
+}
+
+impl DatasetIdentity {
+    pub fn new(dir: &str) -> Self {
+        Self {
+            dir: dir.to_owned(),
+            manifest: None,
+        }
+    }
+
+    /// Identify the configuration data by its directory and the manifest in the directory.
+    pub fn load(dir: &str) -> Result<Self, EmuError> {
+        Ok(Self {
+            dir: dir.to_owned(),
+            manifest: load_manifest(dir)?.map(|m| m.identity()),
+        })
+    }
+}
+
+impl std::fmt::Display for DatasetIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.manifest {
+            Some(manifest) => write!(f, "{} ({})", self.dir, manifest),
+            None => write!(f, "{} (no manifest)", self.dir),
+        }
+    }
+}
+
+/// Result of an MU check together with the configuration data it's based on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalcReport {
+    pub dataset: DatasetIdentity,
+    pub computed: Computed,
+    pub tolerance: Tolerance,
+    pub verdict: Verdict,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dose_prediction: Option<DosePrediction>, // Some if the dose was predicted from MU(plan)
+}
+
+impl CalcReport {
+    /// Create the report and judge the MU difference using the matching tolerance rule.
+    pub fn new(dataset: DatasetIdentity, computed: Computed, tolerances: &ToleranceTable) -> Self {
+        let cp = &computed.calc_param;
+        let tolerance = tolerances.get(&cp.machine, cp.energy, &cp.applicator);
+        let verdict = tolerance.verdict(computed.difference);
+        Self {
+            dataset,
+            computed,
+            tolerance,
+            verdict,
+            dose_prediction: None,
+        }
+    }
+
+    /// Add the dose at zref predicted from the planned MUs to the report.
+    pub fn with_dose_prediction(mut self) -> Result<Self, EmuError> {
+        self.dose_prediction = Some(self.computed.dose_prediction()?);
+        Ok(self)
+    }
+
+    pub fn to_json(&self) -> Result<String, EmuError> {
+        serde_json::to_string_pretty(self).map_err(|e| EmuError::Str(e.to_string()))
+    }
+
+    /// JSON array with the reports of several beams, e.g. the beams of an RT Plan.
+    pub fn list_to_json(reports: &[CalcReport]) -> Result<String, EmuError> {
+        serde_json::to_string_pretty(reports).map_err(|e| EmuError::Str(e.to_string()))
+    }
+}
+
+impl std::fmt::Display for CalcReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Calculation parameters:\n{}", self.computed.calc_param)?;
+        if let Some(conversion) = &self.computed.pdd_conversion {
+            writeln!(
+                f,
+                "Dose at zref derived from the prescription: PDD(depth) {}%, PDD(zref) {}%",
+                conversion.pdd_depth, conversion.pdd_zref
+            )?;
+        }
+        if let Some(ipol) = &self.computed.of_ipol.energy_ipol {
+            writeln!(
+                f,
+                "Energy interpolated between {} MeV and {} MeV",
+                ipol.energy0, ipol.energy1
+            )?;
+        }
+        if let Some(method) = self.computed.of_ipol.method {
+            if method != InterpolationMethod::Linear {
+                writeln!(f, "SSD interpolation: {}", method)?;
+            }
+        }
+        if !self.computed.interpolation_comparison.is_empty() {
+            writeln!(f, "MU(check) per SSD interpolation method:")?;
+            for c in &self.computed.interpolation_comparison {
+                writeln!(
+                    f,
+                    "  {}: output factor {:.4}, MU {:.4} ({:+.3}%)",
+                    c.method, c.cf_of, c.mu, c.difference
+                )?;
+            }
+        }
+        if let Some(fit) = &self.computed.of_ipol.virtual_source {
+            writeln!(
+                f,
+                "Output factor from the virtual source model{}: virtual SSD[cm] {:.2} at SSD[cm] {}, \
+                output factor {:.4}",
+                if fit.extrapolated {
+                    " (extrapolated)"
+                } else {
+                    ""
+                },
+                fit.virtual_ssd,
+                fit.ssd_ref,
+                self.computed.cf_of
+            )?;
+            writeln!(
+                f,
+                "Fit residuals (tabulated - model, max {:.4}):",
+                fit.max_residual()
+            )?;
+            for (ssd, residual) in &fit.residuals {
+                writeln!(f, "  SSD[cm] {}: {:+.4}", ssd, residual)?;
+            }
+        }
+        if self.computed.cf_fda_derived {
+            writeln!(
+                f,
+                "Cutout factor {:.4} derived from the FDA table (not measured)",
+                self.computed.cf_fda
+            )?;
+        }
+        if let Some(b) = &self.computed.bolus {
+            writeln!(
+                f,
+                "Bolus: output factor at SSD[cm] {} (bolus surface), \
+                zref at depth[cm] {} below the skin",
+                b.ssd, b.depth_zref
+            )?;
+        }
+        for factor in &self.computed.factors {
+            writeln!(f, "Correction factor {}", factor)?;
+        }
+        if let Some(calibration) = &self.computed.calibration {
+            writeln!(f, "Calibration: {}", calibration)?;
+        }
+        if let Some(u) = &self.computed.uncertainty {
+            writeln!(f, "Standard uncertainty of MU(check): {}", u)?;
+        }
+        if let Some(prediction) = &self.dose_prediction {
+            writeln!(f, "Dose(zref) predicted from MU(plan): {}", prediction)?;
+        }
+        write!(
+            f,
+            "MU(check): {:.4}\nDifference[%]: {:.6}\n\
+            Verdict: {} (tolerance[%]: warning > {}, fail > {})\nDataset: {}",
+            self.computed.mu,
+            self.computed.difference,
+            self.verdict,
+            self.tolerance.warning,
+            self.tolerance.fail,
+            self.dataset
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::correction_data_set_load_data;
+
+    #[test]
+    fn calc_report_to_json() {
+        let cds = async_std::task::block_on(correction_data_set_load_data("resources")).unwrap();
+        let computed = cds
+            .calc("Synergy2", "10x10", "2.5x10", "12", "99.2", "118", "100")
+            .unwrap();
+        let report = CalcReport::new(
+            DatasetIdentity::new("resources"),
+            computed,
+            &ToleranceTable::default(),
+        );
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["dataset"]["dir"], "resources");
+        assert_eq!(json["computed"]["calc_param"]["fda_id"], 9);
+        assert_eq!(
+            json["verdict"],
+            serde_json::to_value(report.verdict).unwrap()
+        );
+        assert!(json.get("dose_prediction").is_none());
+
+        let json: serde_json::Value =
+            serde_json::from_str(&CalcReport::list_to_json(&[report.clone(), report]).unwrap())
+                .unwrap();
+        assert_eq!(json.as_array().map(|v| v.len()), Some(2));
+    }
+}
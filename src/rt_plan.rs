@@ -0,0 +1,264 @@
+use crate::dicom::{parse_dicom, DataSet, Tag};
+use crate::{resolve_fda, CalcParam, CorrectionData, EmuError};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const MODALITY: Tag = (0x0008, 0x0060);
+const BEAM_SEQUENCE: Tag = (0x300A, 0x00B0);
+const TREATMENT_MACHINE_NAME: Tag = (0x300A, 0x00B2);
+const BEAM_NUMBER: Tag = (0x300A, 0x00C0);
+const BEAM_NAME: Tag = (0x300A, 0x00C2);
+const RADIATION_TYPE: Tag = (0x300A, 0x00C6);
+const BLOCK_SEQUENCE: Tag = (0x300A, 0x00F4);
+const BLOCK_TRAY_ID: Tag = (0x300A, 0x00F5);
+const BLOCK_NAME: Tag = (0x300A, 0x00FE);
+const APPLICATOR_SEQUENCE: Tag = (0x300A, 0x0107);
+const APPLICATOR_ID: Tag = (0x300A, 0x0108);
+const CONTROL_POINT_SEQUENCE: Tag = (0x300A, 0x0111);
+const NOMINAL_BEAM_ENERGY: Tag = (0x300A, 0x0114);
+const SOURCE_TO_SURFACE_DISTANCE: Tag = (0x300A, 0x0130);
+const FRACTION_GROUP_SEQUENCE: Tag = (0x300A, 0x0070);
+const REFERENCED_BEAM_SEQUENCE: Tag = (0x300C, 0x0004);
+const REFERENCED_BEAM_NUMBER: Tag = (0x300C, 0x0006);
+const BEAM_DOSE: Tag = (0x300A, 0x0084);
+const BEAM_METERSET: Tag = (0x300A, 0x0086);
+
+/// Electron beam parameters imported from a DICOM RT Plan.
+/// Values that are absent in the plan are set to zero (or an empty string).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RtPlanBeam {
+    pub number: usize,
+    pub name: String,
+    pub machine: String,
+    pub energy: f64,
+    pub applicator: String,
+    pub block: String, // block or cutout name, empty if the beam has no cutout
+    pub ssd: f64,      // [cm]
+    pub beam_mu: f64,
+    pub dose: f64, // beam dose per fraction [cGy]
+}
+
+impl RtPlanBeam {
+    fn label(&self) -> String {
+        if self.name.is_empty() {
+            self.number.to_string()
+        } else {
+            self.name.clone()
+        }
+    }
+
+    /// Map the beam onto the calculation parameters using the matching correction data.
+    /// The FDA is looked up by the block name, or by the applicator name if the beam
+    /// has no block. A block that isn't in the FDA table is an error.
+    pub fn to_calc_param(&self, vcd: &[CorrectionData]) -> Result<CalcParam, EmuError> {
+        self.map_calc_param(vcd)
+            .map_err(|e| EmuError::RtPlanBeam(self.label(), Box::new(e)))
+    }
+
+    fn map_calc_param(&self, vcd: &[CorrectionData]) -> Result<CalcParam, EmuError> {
+        if !vcd.iter().any(|cd| cd.machine == self.machine) {
+            return Err(EmuError::MachineNotFound(self.machine.clone()));
+        }
+        let cd = vcd
+            .iter()
+            .find(|cd| cd.machine == self.machine && cd.applicator == self.applicator)
+            .ok_or_else(|| EmuError::ApplicatorNotFound(self.applicator.clone()))?;
+        let depth_zref = cd
+            .get_zref(self.energy)
+            .ok_or(EmuError::EnergyNotFound(self.energy))?;
+        let fda_name = if self.block.is_empty() {
+            &self.applicator
+        } else {
+            &self.block
+        };
+        let fda_id = resolve_fda(&cd.fda, fda_name)?;
+        Ok(CalcParam {
+            machine: cd.machine.clone(),
+            applicator: cd.applicator.clone(),
+            energy: self.energy,
+            ssd: self.ssd,
+            depth_zref,
+            dose_zref: self.dose,
+            planned_beam_mu: self.beam_mu,
+            fda_id,
+            ..CalcParam::new()
+        })
+    }
+}
+
+fn get_beam(ds: &DataSet) -> RtPlanBeam {
+    let number = ds.get_f64(BEAM_NUMBER).unwrap_or(0.0) as usize;
+    let block = ds
+        .get_items(BLOCK_SEQUENCE)
+        .first()
+        .and_then(|b| {
+            b.get_str(BLOCK_NAME)
+                .filter(|s| !s.is_empty())
+                .or_else(|| b.get_str(BLOCK_TRAY_ID))
+        })
+        .unwrap_or_default();
+    let applicator = ds
+        .get_items(APPLICATOR_SEQUENCE)
+        .first()
+        .and_then(|a| a.get_str(APPLICATOR_ID))
+        .unwrap_or_default();
+    // Energy and SSD are defined in the first control point.
+    let cp0 = ds.get_items(CONTROL_POINT_SEQUENCE).first();
+    let energy = cp0
+        .and_then(|cp| cp.get_f64(NOMINAL_BEAM_ENERGY))
+        .unwrap_or(0.0);
+    let ssd = cp0
+        .and_then(|cp| cp.get_f64(SOURCE_TO_SURFACE_DISTANCE))
+        .map(|ssd| ssd / 10.0)
+        .unwrap_or(0.0);
+    RtPlanBeam {
+        number,
+        name: ds.get_str(BEAM_NAME).unwrap_or_default(),
+        machine: ds.get_str(TREATMENT_MACHINE_NAME).unwrap_or_default(),
+        energy,
+        applicator,
+        block,
+        ssd,
+        beam_mu: 0.0,
+        dose: 0.0,
+    }
+}
+
+/// Extract the electron beams from the content of a DICOM RT Plan file.
+pub fn parse_rt_plan(bytes: &[u8]) -> Result<Vec<RtPlanBeam>, EmuError> {
+    let ds = parse_dicom(bytes)?;
+    let modality = ds.get_str(MODALITY).unwrap_or_default();
+    if modality != "RTPLAN" {
+        return Err(EmuError::RtPlan(format!(
+            "Expected modality RTPLAN but found [{}]",
+            modality
+        )));
+    }
+    let mut beams = vec![];
+    for item in ds.get_items(BEAM_SEQUENCE) {
+        if item.get_str(RADIATION_TYPE).unwrap_or_default() == "ELECTRON" {
+            beams.push(get_beam(item));
+        }
+    }
+    if beams.is_empty() {
+        return Err(EmuError::RtPlan("No electron beams found".to_owned()));
+    }
+    // The MUs and beam dose are stored per fraction group.
+    for fg in ds.get_items(FRACTION_GROUP_SEQUENCE) {
+        for rb in fg.get_items(REFERENCED_BEAM_SEQUENCE) {
+            let number = rb.get_f64(REFERENCED_BEAM_NUMBER).unwrap_or(-1.0);
+            for beam in beams.iter_mut() {
+                if beam.number as f64 == number {
+                    beam.beam_mu = rb.get_f64(BEAM_METERSET).unwrap_or(0.0);
+                    beam.dose = rb.get_f64(BEAM_DOSE).unwrap_or(0.0) * 100.0;
+                }
+            }
+        }
+    }
+    Ok(beams)
+}
+
+/// Read the electron beams from a DICOM RT Plan file.
+pub fn read_rt_plan(path_buf: PathBuf) -> Result<Vec<RtPlanBeam>, EmuError> {
+    let bytes =
+        std::fs::read(&path_buf).map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    parse_rt_plan(&bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dicom::test::{explicit_element, explicit_str, item, part10};
+    use crate::{calculate_mu, load_data};
+
+    fn build_beam(
+        number: &str,
+        name: &str,
+        radiation: &str,
+        applicator: &str,
+        block: &str,
+    ) -> Vec<u8> {
+        let mut v = explicit_str(TREATMENT_MACHINE_NAME, b"SH", "Synergy2");
+        v.extend(explicit_str(BEAM_NUMBER, b"IS", number));
+        v.extend(explicit_str(BEAM_NAME, b"LO", name));
+        v.extend(explicit_str(RADIATION_TYPE, b"CS", radiation));
+        if !block.is_empty() {
+            let b = explicit_str(BLOCK_NAME, b"LO", block);
+            v.extend(explicit_element(BLOCK_SEQUENCE, b"SQ", &item(&b)));
+        }
+        let a = explicit_str(APPLICATOR_ID, b"SH", applicator);
+        v.extend(explicit_element(APPLICATOR_SEQUENCE, b"SQ", &item(&a)));
+        let mut cp = explicit_str(NOMINAL_BEAM_ENERGY, b"DS", "12");
+        cp.extend(explicit_str(SOURCE_TO_SURFACE_DISTANCE, b"DS", "992"));
+        v.extend(explicit_element(CONTROL_POINT_SEQUENCE, b"SQ", &item(&cp)));
+        v
+    }
+
+    fn build_rt_plan() -> Vec<u8> {
+        let mut ds = explicit_str(MODALITY, b"CS", "RTPLAN");
+        let mut beams = item(&build_beam("1", "E1", "ELECTRON", "10x10", "2.5x10"));
+        beams.extend(item(&build_beam("2", "P1", "PHOTON", "10x10", "")));
+        beams.extend(item(&build_beam("3", "E2", "ELECTRON", "6x6", "")));
+        beams.extend(item(&build_beam("4", "E3", "ELECTRON", "5x5", "")));
+        ds.extend(explicit_element(BEAM_SEQUENCE, b"SQ", &beams));
+        let mut rbs = vec![];
+        for (number, mu, dose) in &[("1", "118.046388925549", "1"), ("3", "110.5", "2")] {
+            let mut rb = explicit_str(BEAM_DOSE, b"DS", dose);
+            rb.extend(explicit_str(BEAM_METERSET, b"DS", mu));
+            rb.extend(explicit_str(REFERENCED_BEAM_NUMBER, b"IS", number));
+            rbs.extend(item(&rb));
+        }
+        let fg = explicit_element(REFERENCED_BEAM_SEQUENCE, b"SQ", &rbs);
+        ds.extend(explicit_element(FRACTION_GROUP_SEQUENCE, b"SQ", &item(&fg)));
+        part10("1.2.840.10008.1.2.1", &ds)
+    }
+
+    #[test]
+    fn rt_plan_beams() {
+        let beams = parse_rt_plan(&build_rt_plan()).unwrap();
+        assert_eq!(beams.len(), 3);
+        let beam = &beams[0];
+        assert_eq!(beam.number, 1);
+        assert_eq!(beam.machine, "Synergy2");
+        assert_eq!(beam.applicator, "10x10");
+        assert_eq!(beam.block, "2.5x10");
+        assert_eq!(beam.energy, 12.0);
+        assert_eq!(beam.ssd, 99.2);
+        assert_eq!(beam.beam_mu, 118.046388925549);
+        assert_eq!(beam.dose, 100.0);
+        assert_eq!(beams[1].dose, 200.0);
+    }
+
+    #[test]
+    fn rt_plan_to_calc_param() {
+        let vcd = async_std::task::block_on(load_data("resources")).unwrap();
+        let beams = parse_rt_plan(&build_rt_plan()).unwrap();
+
+        let cp = beams[0].to_calc_param(&vcd).unwrap();
+        assert_eq!(cp.applicator, "10x10");
+        assert_eq!(cp.fda_id, 9);
+        assert_eq!(cp.depth_zref, 2.78);
+        let cd = vcd.iter().find(|cd| cd.applicator == "10x10").unwrap();
+        let mu = calculate_mu(&cp, cd).unwrap();
+        assert!((mu - cp.planned_beam_mu).abs() < f32::EPSILON as f64);
+
+        // Without a block, the FDA of the open applicator is used.
+        let cp = beams[1].to_calc_param(&vcd).unwrap();
+        assert_eq!(cp.fda_id, 1);
+
+        match beams[2].to_calc_param(&vcd) {
+            Err(EmuError::RtPlanBeam(name, e)) => {
+                assert_eq!(name, "E3");
+                assert!(matches!(*e, EmuError::ApplicatorNotFound(_)));
+            }
+            _ => panic!("Expected an unmapped applicator"),
+        }
+    }
+
+    #[test]
+    fn rt_plan_invalid_modality() {
+        let ds = explicit_str(MODALITY, b"CS", "RTDOSE");
+        let bytes = part10("1.2.840.10008.1.2.1", &ds);
+        assert!(matches!(parse_rt_plan(&bytes), Err(EmuError::RtPlan(_))));
+    }
+}
@@ -0,0 +1,80 @@
+use crate::energy_table::{EnergyTable, TableKind};
+use crate::errors::EmuError;
+use crate::{CalcParam, CorrectionData};
+
+/// Prefix of the files with the backscatter factors of internal shields.
+pub const SHIELD_PREFIX: &str = "shield_";
+
+/// Marker of the backscatter factor of an internal (lead or tungsten) shield per energy as a
+/// function of the depth [cm] of the shield interface: the dose at zref, upstream of a shield
+/// at that depth, relative to the dose at zref without shield.
+#[derive(Debug, Clone)]
+pub struct Shield;
+
+impl TableKind for Shield {
+    const PREFIX: &'static str = SHIELD_PREFIX;
+    const LABEL: &'static str = "Depth";
+    const VARIABLE: &'static str = "depth [cm]";
+    const FACTOR: &'static str = "backscatter factor";
+
+    fn out_of_range(depth: f64) -> EmuError {
+        EmuError::ShieldDepthNotFound(depth)
+    }
+
+    fn table(cd: &CorrectionData) -> Option<&ShieldTable> {
+        cd.shield.as_ref()
+    }
+
+    fn set_table(cd: &mut CorrectionData, table: ShieldTable) {
+        cd.shield = Some(table);
+    }
+}
+
+/// Backscatter factor at zref of an internal shield per energy as a function of the depth [cm]
+/// of the shield interface.
+pub type ShieldTable = EnergyTable<Shield>;
+
+impl CorrectionData {
+    /// Get the backscatter factor at zref of an internal shield at the depth of its interface
+    /// below the skin (looked up below the bolus surface with bolus). None without shield.
+    /// The shield must lie at or below zref, the table doesn't give the dose downstream of it.
+    pub fn get_shield_factor(&self, calc_param: &CalcParam) -> Result<Option<f64>, EmuError> {
+        let depth = match calc_param.shield_depth {
+            Some(depth) => depth,
+            None => return Ok(None),
+        };
+        if !(depth.is_finite() && depth > 0.0) {
+            return Err(EmuError::InvalidInput(
+                "shield_depth".to_owned(),
+                depth.to_string(),
+            ));
+        }
+        let shield = self.shield.as_ref().ok_or_else(|| {
+            EmuError::ShieldTableNotFound(self.machine.clone(), self.applicator.clone())
+        })?;
+        let depth = depth
+            + calc_param
+                .bolus_adjustment()?
+                .map_or(0.0, |b| b.water_equivalent_thickness());
+        let depth_zref = if calc_param.has_depth_zref() {
+            calc_param.depth_zref
+        } else {
+            self.get_zref(calc_param.energy)
+                .ok_or(EmuError::EnergyNotFound(calc_param.energy))?
+        };
+        if depth < depth_zref {
+            return Err(EmuError::InvalidInput(
+                "shield_depth".to_owned(),
+                format!(
+                    "{} (the shield lies upstream of zref at {} cm)",
+                    depth, depth_zref
+                ),
+            ));
+        }
+        Ok(Some(shield.get_cf(
+            calc_param.energy,
+            depth,
+            self.options.energy_interpolation,
+        )?))
+    }
+}
@@ -0,0 +1,245 @@
+use crate::errors::EmuError;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Name of the tolerance file in the directory with the configuration data.
+pub const TOLERANCE_FILE_NAME: &str = "tolerances.csv";
+
+/// Outcome of an MU check compared to the tolerance levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Verdict {
+    Pass,
+    Warning,
+    Fail,
+}
+
+impl Verdict {
+    /// Process exit code of the verdict (1 is reserved for errors).
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Verdict::Pass => 0,
+            Verdict::Warning => 2,
+            Verdict::Fail => 3,
+        }
+    }
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Verdict::Pass => write!(f, "PASS"),
+            Verdict::Warning => write!(f, "WARNING"),
+            Verdict::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// Tolerance levels on the absolute MU difference [%].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Tolerance {
+    pub warning: f64,
+    pub fail: f64,
+}
+
+impl Tolerance {
+    /// A difference up to the warning level passes, up to the fail level results in a warning.
+    pub fn verdict(&self, difference: f64) -> Verdict {
+        let d = difference.abs();
+        if d <= self.warning {
+            Verdict::Pass
+        } else if d <= self.fail {
+            Verdict::Warning
+        } else {
+            Verdict::Fail
+        }
+    }
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            warning: 3.0,
+            fail: 5.0,
+        }
+    }
+}
+
+/// Tolerance for a machine, energy and applicator. A field set to None matches any value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToleranceRule {
+    pub machine: Option<String>,
+    pub energy: Option<f64>,
+    pub applicator: Option<String>,
+    pub tolerance: Tolerance,
+}
+
+impl ToleranceRule {
+    fn matches(&self, machine: &str, energy: f64, applicator: &str) -> bool {
+        self.machine.as_ref().map_or(true, |m| m == machine)
+            && self
+                .energy
+                .map_or(true, |e| (e - energy).abs() < f64::EPSILON)
+            && self.applicator.as_ref().map_or(true, |a| a == applicator)
+    }
+
+    fn specificity(&self) -> usize {
+        self.machine.is_some() as usize
+            + self.energy.is_some() as usize
+            + self.applicator.is_some() as usize
+    }
+}
+
+/// Set of tolerance rules with a fallback for beams that don't match any rule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToleranceTable {
+    pub rules: Vec<ToleranceRule>,
+    pub fallback: Tolerance,
+}
+
+impl ToleranceTable {
+    pub fn new() -> Self {
+        Self {
+            rules: vec![],
+            fallback: Tolerance::default(),
+        }
+    }
+
+    /// Get the tolerance of the most specific matching rule.
+    /// If several rules are equally specific, the first one is used.
+    pub fn get(&self, machine: &str, energy: f64, applicator: &str) -> Tolerance {
+        let mut opt_rule: Option<&ToleranceRule> = None;
+        for rule in &self.rules {
+            if rule.matches(machine, energy, applicator)
+                && opt_rule.map_or(true, |r| rule.specificity() > r.specificity())
+            {
+                opt_rule = Some(rule);
+            }
+        }
+        opt_rule.map_or(self.fallback, |r| r.tolerance)
+    }
+
+    pub fn verdict(
+        &self,
+        machine: &str,
+        energy: f64,
+        applicator: &str,
+        difference: f64,
+    ) -> Verdict {
+        self.get(machine, energy, applicator).verdict(difference)
+    }
+}
+
+impl Default for ToleranceTable {
+    fn default() -> Self {
+        ToleranceTable::new()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ToleranceRecord {
+    machine: String,
+    energy: String,
+    applicator: String,
+    warning: f64,
+    fail: f64,
+}
+
+fn wildcard(s: &str) -> Option<String> {
+    if s.is_empty() || s == "*" {
+        None
+    } else {
+        Some(s.to_owned())
+    }
+}
+
+fn parse_tolerance_table<R: std::io::Read>(rdr: R) -> Result<ToleranceTable, EmuError> {
+    let mut table = ToleranceTable::new();
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(rdr);
+    for record in rdr.deserialize::<ToleranceRecord>() {
+        let record = record.map_err(|e| EmuError::Format(e.to_string()))?;
+        let energy = match wildcard(&record.energy) {
+            None => None,
+            Some(s) => Some(
+                s.parse::<f64>()
+                    .map_err(|e| EmuError::Format(format!("{}: {}", s, e)))?,
+            ),
+        };
+        if record.warning < 0.0 || record.warning > record.fail {
+            return Err(EmuError::Format(format!(
+                "Tolerance levels must satisfy 0 <= warning <= fail [{} <-> {}]",
+                record.warning, record.fail
+            )));
+        }
+        table.rules.push(ToleranceRule {
+            machine: wildcard(&record.machine),
+            energy,
+            applicator: wildcard(&record.applicator),
+            tolerance: Tolerance {
+                warning: record.warning,
+                fail: record.fail,
+            },
+        });
+    }
+    Ok(table)
+}
+
+/// Read the tolerance rules from a CSV file with the header
+/// `machine,energy,applicator,warning,fail`. A `*` matches any machine, energy or applicator.
+pub fn read_tolerance_table(path_buf: PathBuf) -> Result<ToleranceTable, EmuError> {
+    let file = std::fs::File::open(&path_buf)
+        .map_err(|e| EmuError::IO(format!("{:#?}: {}", path_buf, e)))?;
+    parse_tolerance_table(file).map_err(|e| EmuError::Format(format!("{:#?}: {}", path_buf, e)))
+}
+
+/// Load the tolerance rules stored next to the configuration data.
+/// If the directory has no tolerance file, the default tolerance levels are used.
+pub fn load_tolerance_table(dirname: &str) -> Result<ToleranceTable, EmuError> {
+    let path_buf = Path::new(dirname).join(TOLERANCE_FILE_NAME);
+    if path_buf.is_file() {
+        read_tolerance_table(path_buf)
+    } else {
+        Ok(ToleranceTable::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tolerance_rules() {
+        let data = "\
+machine,energy,applicator,warning,fail
+*,*,*,2,4
+Synergy2,*,*,3,5
+Synergy2,4,*,4,6
+Synergy2,4,6x6,5,7
+";
+        let table = parse_tolerance_table(data.as_bytes()).unwrap();
+        assert_eq!(table.get("Synergy2", 4.0, "6x6").fail, 7.0);
+        assert_eq!(table.get("Synergy2", 4.0, "10x10").fail, 6.0);
+        assert_eq!(table.get("Synergy2", 6.0, "10x10").fail, 5.0);
+        assert_eq!(table.get("Other", 6.0, "10x10").fail, 4.0);
+
+        assert_eq!(table.verdict("Other", 6.0, "10x10", -1.5), Verdict::Pass);
+        assert_eq!(table.verdict("Other", 6.0, "10x10", -2.5), Verdict::Warning);
+        assert_eq!(table.verdict("Other", 6.0, "10x10", 4.5), Verdict::Fail);
+
+        // fallback
+        let table = ToleranceTable::default();
+        assert_eq!(table.verdict("Other", 6.0, "10x10", 3.0), Verdict::Pass);
+        assert_eq!(table.verdict("Other", 6.0, "10x10", 4.0), Verdict::Warning);
+        assert_eq!(table.verdict("Other", 6.0, "10x10", 5.1), Verdict::Fail);
+    }
+
+    #[test]
+    fn tolerance_invalid() {
+        let data = "machine,energy,applicator,warning,fail\n*,*,*,5,4\n";
+        assert!(parse_tolerance_table(data.as_bytes()).is_err());
+        let data = "machine,energy,applicator,warning,fail\n*,x,*,3,4\n";
+        assert!(parse_tolerance_table(data.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::correction_data::find_correction_data;
+use crate::ipol::energy_column;
+use crate::{
+    read_fda_table_with_format, read_of_table_with_format, CalcParam, CorrectionData, CsvFormat,
+    EmuError, FdaTable, OFTable,
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Prefix of the files with the uncertainties of the output factors.
+pub const UNC_OF_PREFIX: &str = "unc_of_";
+/// Prefix of the files with the uncertainties of the field defining aperture factors.
+pub const UNC_FDA_PREFIX: &str = "unc_fda_";
+
+/// Relative standard uncertainties [%] of the tabulated factors of a machine and applicator.
+/// The tables have the same layout as the output factor and field defining aperture tables.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Uncertainties {
+    pub output_factors: Option<OFTable>,
+    pub fda: Option<FdaTable>,
+}
+
+impl Uncertainties {
+    pub fn is_empty(&self) -> bool {
+        self.output_factors.is_none() && self.fda.is_none()
+    }
+}
+
+/// Relative standard uncertainties [%] (k=1) of the components of the check MU and their
+/// combination, assuming the components are uncorrelated.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MuUncertainty {
+    pub dose: f64,
+    pub output_factor: f64,
+    pub fda: f64,
+    pub combined: f64,
+    pub mu: f64, // standard uncertainty of the check MU [MU]
+}
+
+impl std::fmt::Display for MuUncertainty {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.4} MU ({:.2}%, k=1) [dose: {:.2}%, output factor: {:.2}%, FDA: {:.2}%]",
+            self.mu, self.combined, self.dose, self.output_factor, self.fda
+        )
+    }
+}
+
+// Check that an uncertainty table has the layout of the table with the factors.
+pub(crate) fn check_uncertainty_tables(cd: &CorrectionData) -> Result<(), String> {
+    if let Some(unc) = &cd.uncertainties.output_factors {
+        if unc.energies != cd.output_factors.energies || unc.ssds != cd.output_factors.ssds {
+            return Err(format!(
+                "The uncertainties of the output factors of machine [{}] and applicator [{}] \
+                must have the same energies and SSDs as the output factors",
+                cd.machine, cd.applicator
+            ));
+        }
+    }
+    if let Some(unc) = &cd.uncertainties.fda {
+        if unc.energies != cd.fda.energies || unc.ids != cd.fda.ids {
+            return Err(format!(
+                "The uncertainties of the FDA factors of machine [{}] and applicator [{}] \
+                must have the same energies and FDAs as the FDA factors",
+                cd.machine, cd.applicator
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Read the uncertainty tables (unc_of_*.csv and unc_fda_*.csv) in a directory
+/// and add them to the matching correction data.
+pub fn load_uncertainties(
+    dirname: &str,
+    opt_format: Option<CsvFormat>,
+    vcd: &mut [CorrectionData],
+) -> Result<(), EmuError> {
+    let dir = PathBuf::from(dirname);
+    for entry in std::fs::read_dir(&dir)? {
+        let ep = entry?.path();
+        let file_name = ep
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("")
+            .to_owned();
+        let cd = if file_name.starts_with(UNC_OF_PREFIX) {
+            let (machine, applicator, table) = read_of_table_with_format(ep.clone(), opt_format)?;
+            let cd = find_correction_data(vcd, &machine, &applicator, &ep)?;
+            cd.uncertainties.output_factors = Some(table);
+            cd
+        } else if file_name.starts_with(UNC_FDA_PREFIX) {
+            let (machine, applicator, table) = read_fda_table_with_format(ep.clone(), opt_format)?;
+            let cd = find_correction_data(vcd, &machine, &applicator, &ep)?;
+            cd.uncertainties.fda = Some(table);
+            cd
+        } else {
+            continue;
+        };
+        check_uncertainty_tables(cd).map_err(|e| EmuError::Format(format!("{:#?}: {}", ep, e)))?;
+    }
+    Ok(())
+}
+
+impl CorrectionData {
+    /// Propagate the uncertainties of the dose, output factor and FDA factor to the check MU.
+    /// The output factor uncertainty is interpolated linearly between the SSDs (the nearest SSD
+    /// is used outside the tabulated SSDs). A cutout uses the largest FDA uncertainty of the
+    /// energy. None if no uncertainties are available.
+    pub fn get_mu_uncertainty(
+        &self,
+        calc_param: &CalcParam,
+        mu: f64,
+    ) -> Result<Option<MuUncertainty>, EmuError> {
+        let dose = self.options.dose_uncertainty;
+        if self.uncertainties.is_empty() && dose == 0.0 {
+            return Ok(None);
+        }
+        let energy_interpolation = self.options.energy_interpolation;
+        let output_factor = match &self.uncertainties.output_factors {
+            Some(unc) => {
+                let min = unc.ssds.iter().cloned().fold(f64::MAX, f64::min);
+                let max = unc.ssds.iter().cloned().fold(f64::MIN, f64::max);
+                let ssd = calc_param.ssd.max(min).min(max);
+                if energy_interpolation {
+                    unc.get_cf_ipol_energy(calc_param.energy, ssd)?.cf
+                } else {
+                    unc.get_cf(calc_param.energy, ssd)?
+                }
+            }
+            None => 0.0,
+        };
+        let fda = match &self.uncertainties.fda {
+            Some(unc) if calc_param.has_cutout() => {
+                let (col, _) = energy_column(
+                    &unc.energies,
+                    &unc.table,
+                    calc_param.energy,
+                    energy_interpolation,
+                )?;
+                col.iter().cloned().fold(0.0, f64::max)
+            }
+            Some(unc) => {
+                if energy_interpolation {
+                    unc.get_cf_ipol_energy(calc_param.energy, calc_param.fda_id)?
+                } else {
+                    unc.get_cf(calc_param.energy, calc_param.fda_id)?
+                }
+            }
+            None => 0.0,
+        };
+        let combined = (dose * dose + output_factor * output_factor + fda * fda).sqrt();
+        Ok(Some(MuUncertainty {
+            dose,
+            output_factor,
+            fda,
+            combined,
+            mu: mu * combined / 100.0,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn mu_uncertainty() {
+        let mut vcd = async_std::task::block_on(crate::load_data("resources")).unwrap();
+        let cd = vcd.iter_mut().find(|cd| cd.applicator == "10x10").unwrap();
+        let mut cp = CalcParam {
+            machine: "Synergy2".to_string(),
+            applicator: "10x10".to_string(),
+            energy: 12.0,
+            ssd: 99.5,
+            depth_zref: 2.78,
+            dose_zref: 100.0,
+            planned_beam_mu: 100.0,
+            fda_id: 9,
+            ..CalcParam::new()
+        };
+        assert!(cd.get_mu_uncertainty(&cp, 100.0).unwrap().is_none());
+
+        let mut unc_of = cd.output_factors.clone();
+        for col in unc_of.table.iter_mut() {
+            for (v, ssd) in col.iter_mut().zip(cd.output_factors.ssds.iter()) {
+                *v = if *ssd < 99.5 { 1.0 } else { 2.0 };
+            }
+        }
+        let mut unc_fda = cd.fda.clone();
+        for col in unc_fda.table.iter_mut() {
+            for v in col.iter_mut() {
+                *v = 1.0;
+            }
+            col[0] = 3.0;
+        }
+        cd.uncertainties.output_factors = Some(unc_of);
+        cd.uncertainties.fda = Some(unc_fda);
+        cd.options.dose_uncertainty = 2.0;
+        assert!(check_uncertainty_tables(cd).is_ok());
+
+        // OF uncertainty interpolated halfway between SSD 99 and 100
+        let u = cd.get_mu_uncertainty(&cp, 200.0).unwrap().unwrap();
+        assert!((u.output_factor - 1.5).abs() < 1e-12);
+        assert_eq!(u.fda, 1.0);
+        assert!((u.combined - (4.0_f64 + 2.25 + 1.0).sqrt()).abs() < 1e-12);
+        assert!((u.mu - 2.0 * u.combined).abs() < 1e-12);
+
+        // the largest FDA uncertainty is used for a cutout, the nearest SSD outside the table
+        cp.cutout = crate::Cutout::parse_rectangle("7x9");
+        cp.ssd = 120.0;
+        let u = cd.get_mu_uncertainty(&cp, 200.0).unwrap().unwrap();
+        assert_eq!(u.fda, 3.0);
+        assert_eq!(u.output_factor, 2.0);
+
+        cd.uncertainties.fda.as_mut().unwrap().ids.pop();
+        assert!(check_uncertainty_tables(cd).is_err());
+    }
+
+    #[test]
+    fn load_uncertainty_tables() {
+        let tmp = std::env::temp_dir().join(format!(
+            "emu_check_load_uncertainties_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&tmp).unwrap();
+        for file_name in &["of_app_10x10.csv", "fda_app_10x10.csv"] {
+            std::fs::copy(Path::new("resources").join(file_name), tmp.join(file_name)).unwrap();
+        }
+        let (machine, applicator, mut unc) =
+            crate::read_of_table(tmp.join("of_app_10x10.csv")).unwrap();
+        for col in unc.table.iter_mut() {
+            col.iter_mut().for_each(|v| *v = 0.5);
+        }
+        crate::write_of_table(
+            tmp.join("unc_of_app_10x10.csv"),
+            &machine,
+            &applicator,
+            &unc,
+        )
+        .unwrap();
+        let dirname = tmp.to_str().unwrap();
+        let vcd = async_std::task::block_on(crate::load_data(dirname)).unwrap();
+        assert_eq!(vcd.len(), 1);
+        assert!(vcd[0].uncertainties.output_factors.is_some());
+        assert!(vcd[0].uncertainties.fda.is_none());
+
+        crate::write_of_table(tmp.join("unc_of_app_6x6.csv"), &machine, "6x6", &unc).unwrap();
+        assert!(async_std::task::block_on(crate::load_data(dirname)).is_err());
+        std::fs::remove_dir_all(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,686 @@
+use crate::correction_data::get_list_data_files;
+use crate::{
+    load_csv_format, load_manifest, read_calibration_table, read_energy_table,
+    read_fda_table_with_format, read_of_table_with_format, verify_manifest, CsvFormat, EmuError,
+    EnergyTable, FdaTable, OFTable, Obliquity, Pdd, Shield, TableKind, CALIBRATION_FILE_NAME,
+    MANIFEST_FILE_NAME, UNC_FDA_PREFIX, UNC_OF_PREFIX,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Reference conditions under which the output factor must be 1.0.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferenceConditions {
+    pub applicator: String,
+    pub ssd: f64,
+}
+
+impl Default for ReferenceConditions {
+    fn default() -> Self {
+        Self {
+            applicator: "10x10".to_owned(),
+            ssd: 95.0,
+        }
+    }
+}
+
+/// Problem found in a file of the configuration data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationIssue {
+    pub file: PathBuf,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn new(file: &Path, message: String) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.message)
+    }
+}
+
+// Table that was read successfully, together with the file it was read from.
+struct LoadedTable<T> {
+    file: PathBuf,
+    machine: String,
+    applicator: String,
+    table: T,
+}
+
+fn is_invalid_factor(v: f64) -> bool {
+    v.is_nan() || v < 0.0
+}
+
+fn check_duplicates<T>(tables: &[LoadedTable<T>], kind: &str, issues: &mut Vec<ValidationIssue>) {
+    for (i, lt) in tables.iter().enumerate() {
+        if let Some(first) = tables[..i]
+            .iter()
+            .find(|t| t.machine == lt.machine && t.applicator == lt.applicator)
+        {
+            issues.push(ValidationIssue::new(
+                &lt.file,
+                format!(
+                    "Duplicate {} table for machine [{}] and applicator [{}], also defined in {}",
+                    kind,
+                    lt.machine,
+                    lt.applicator,
+                    first.file.display()
+                ),
+            ));
+        }
+    }
+}
+
+// Check that the tabulated values (e.g. SSDs) are numbers in ascending order without duplicates.
+fn check_sorted(file: &Path, name: &str, xs: &[f64], issues: &mut Vec<ValidationIssue>) {
+    for x in xs.iter().filter(|x| x.is_nan()) {
+        issues.push(ValidationIssue::new(
+            file,
+            format!("Invalid {} [{}]", name, x),
+        ));
+    }
+    for w in xs.windows(2) {
+        if w[1] == w[0] {
+            issues.push(ValidationIssue::new(
+                file,
+                format!("Duplicate {} [{}]", name, w[1]),
+            ));
+        } else if w[1] < w[0] {
+            issues.push(ValidationIssue::new(
+                file,
+                format!(
+                    "{}s are not sorted in ascending order [{} -> {}]",
+                    name, w[0], w[1]
+                ),
+            ));
+        }
+    }
+}
+
+// Output factor table of the same machine and applicator, reported as an issue if there's none.
+fn find_of_table<'a, T>(
+    ofs: &'a [LoadedTable<OFTable>],
+    lt: &LoadedTable<T>,
+    kind: &str,
+    issues: &mut Vec<ValidationIssue>,
+) -> Option<&'a LoadedTable<OFTable>> {
+    let of = ofs
+        .iter()
+        .find(|of| of.machine == lt.machine && of.applicator == lt.applicator);
+    if of.is_none() {
+        issues.push(ValidationIssue::new(
+            &lt.file,
+            format!(
+                "No output factor table for the {} table of machine [{}] and applicator [{}]",
+                kind, lt.machine, lt.applicator
+            ),
+        ));
+    }
+    of
+}
+
+fn check_of_table(
+    lt: &LoadedTable<OFTable>,
+    reference: &ReferenceConditions,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let t = &lt.table;
+    check_sorted(&lt.file, "SSD", &t.ssds, issues);
+    for (energy, col) in t.energies.iter().zip(t.table.iter()) {
+        for (ssd, of) in t.ssds.iter().zip(col.iter()) {
+            if is_invalid_factor(*of) {
+                issues.push(ValidationIssue::new(
+                    &lt.file,
+                    format!(
+                        "Invalid output factor [{}] at SSD [{}] and energy [{}]",
+                        of, ssd, energy
+                    ),
+                ));
+            }
+        }
+    }
+    if lt.applicator != reference.applicator {
+        return;
+    }
+    let opt_idx = t.ssds.iter().position(|ssd| *ssd == reference.ssd);
+    if opt_idx.is_none() {
+        issues.push(ValidationIssue::new(
+            &lt.file,
+            format!("Reference SSD [{}] is missing", reference.ssd),
+        ));
+        return;
+    }
+    let idx = opt_idx.unwrap();
+    for (energy, col) in t.energies.iter().zip(t.table.iter()) {
+        let of = col[idx];
+        if (of - 1.0).abs() > 1e-6 {
+            issues.push(ValidationIssue::new(
+                &lt.file,
+                format!(
+                    "Output factor at the reference SSD [{}] is [{}] instead of 1.0 for energy [{}]",
+                    reference.ssd, of, energy
+                ),
+            ));
+        }
+    }
+}
+
+fn check_fda_table(lt: &LoadedTable<FdaTable>, issues: &mut Vec<ValidationIssue>) {
+    let t = &lt.table;
+    for (i, id) in t.ids.iter().enumerate() {
+        if let Some(j) = t.ids[..i].iter().position(|other| other == id) {
+            issues.push(ValidationIssue::new(
+                &lt.file,
+                format!(
+                    "Duplicate FDA id [{}] for [{}] and [{}]",
+                    id, t.names[j], t.names[i]
+                ),
+            ));
+        }
+    }
+    for (energy, col) in t.energies.iter().zip(t.table.iter()) {
+        for (name, cf) in t.names.iter().zip(col.iter()) {
+            if is_invalid_factor(*cf) {
+                issues.push(ValidationIssue::new(
+                    &lt.file,
+                    format!(
+                        "Invalid correction factor [{}] for FDA [{}] and energy [{}]",
+                        cf, name, energy
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+fn validate_tables(
+    ofs: &[LoadedTable<OFTable>],
+    fdas: &[LoadedTable<FdaTable>],
+    reference: &ReferenceConditions,
+) -> Vec<ValidationIssue> {
+    let mut issues = vec![];
+    check_duplicates(ofs, "output factor", &mut issues);
+    check_duplicates(fdas, "field defining aperture", &mut issues);
+    for of in ofs {
+        match fdas
+            .iter()
+            .find(|fda| fda.machine == of.machine && fda.applicator == of.applicator)
+        {
+            None => issues.push(ValidationIssue::new(
+                &of.file,
+                format!(
+                    "No field defining aperture table for machine [{}] and applicator [{}]",
+                    of.machine, of.applicator
+                ),
+            )),
+            Some(fda) => {
+                if of.table.get_energies() != fda.table.get_energies() {
+                    issues.push(ValidationIssue::new(
+                        &fda.file,
+                        format!(
+                            "Energies {:?} differ from the energies {:?} in {}",
+                            fda.table.get_energies(),
+                            of.table.get_energies(),
+                            of.file.display()
+                        ),
+                    ));
+                }
+            }
+        }
+        check_of_table(of, reference, &mut issues);
+    }
+    for fda in fdas {
+        if !ofs
+            .iter()
+            .any(|of| of.machine == fda.machine && of.applicator == fda.applicator)
+        {
+            issues.push(ValidationIssue::new(
+                &fda.file,
+                format!(
+                    "No output factor table for machine [{}] and applicator [{}]",
+                    fda.machine, fda.applicator
+                ),
+            ));
+        }
+        check_fda_table(fda, &mut issues);
+    }
+    issues
+}
+
+// Files in the directory with a name that starts with the prefix, sorted by name.
+fn list_files(dirname: &str, prefix: &str) -> Result<Vec<PathBuf>, EmuError> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(PathBuf::from(dirname))? {
+        let ep = entry?.path();
+        if ep
+            .file_name()
+            .and_then(|s| s.to_str())
+            .is_some_and(|s| s.starts_with(prefix))
+        {
+            files.push(ep);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Read the tables in the files, a file that can't be read is reported as an issue.
+fn read_tables<T>(
+    files: Vec<PathBuf>,
+    read: impl Fn(PathBuf) -> Result<(String, String, T), EmuError>,
+    issues: &mut Vec<ValidationIssue>,
+) -> Vec<LoadedTable<T>> {
+    let mut tables = vec![];
+    for file in files {
+        match read(file.clone()) {
+            Ok((machine, applicator, table)) => tables.push(LoadedTable {
+                file,
+                machine,
+                applicator,
+                table,
+            }),
+            Err(e) => issues.push(ValidationIssue::new(&file, e.to_string())),
+        }
+    }
+    tables
+}
+
+// Check the tables of a kind (PDD, obliquity or shield): each belongs to an output factor
+// table and has a column for each of its energies, the tabulated values are sorted and the
+// factors are valid.
+fn check_energy_tables<K: TableKind>(
+    tables: &[LoadedTable<EnergyTable<K>>],
+    ofs: &[LoadedTable<OFTable>],
+    kind: &str,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    check_duplicates(tables, kind, issues);
+    for lt in tables {
+        let t = &lt.table;
+        if let Some(of) = find_of_table(ofs, lt, kind, issues) {
+            let missing: Vec<f64> = of
+                .table
+                .get_energies()
+                .iter()
+                .filter(|e| !t.get_energies().contains(e))
+                .cloned()
+                .collect();
+            if !missing.is_empty() {
+                issues.push(ValidationIssue::new(
+                    &lt.file,
+                    format!(
+                        "Energies {:?} of the output factor table {} are missing",
+                        missing,
+                        of.file.display()
+                    ),
+                ));
+            }
+        }
+        check_sorted(&lt.file, K::LABEL, t.get_abscissae(), issues);
+        for (energy, col) in t.energies.iter().zip(t.table.iter()) {
+            for (x, v) in t.get_abscissae().iter().zip(col.iter()) {
+                if is_invalid_factor(*v) {
+                    issues.push(ValidationIssue::new(
+                        &lt.file,
+                        format!(
+                            "Invalid {} [{}] at {} [{}] and energy [{}]",
+                            K::FACTOR,
+                            v,
+                            K::LABEL,
+                            x,
+                            energy
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+}
+
+// Check that each uncertainty table belongs to a table with the same energies and SSDs or FDAs
+// and that the uncertainties are valid.
+fn check_uncertainty_tables(
+    unc_ofs: &[LoadedTable<OFTable>],
+    unc_fdas: &[LoadedTable<FdaTable>],
+    ofs: &[LoadedTable<OFTable>],
+    fdas: &[LoadedTable<FdaTable>],
+    issues: &mut Vec<ValidationIssue>,
+) {
+    check_duplicates(unc_ofs, "output factor uncertainty", issues);
+    check_duplicates(unc_fdas, "FDA uncertainty", issues);
+    for lt in unc_ofs {
+        let t = &lt.table;
+        if let Some(of) = find_of_table(ofs, lt, "uncertainty", issues) {
+            if t.energies != of.table.energies || t.ssds != of.table.ssds {
+                issues.push(ValidationIssue::new(
+                    &lt.file,
+                    format!(
+                        "Energies and SSDs differ from those in {}",
+                        of.file.display()
+                    ),
+                ));
+            }
+        }
+        for col in &t.table {
+            for unc in col.iter().filter(|v| is_invalid_factor(**v)) {
+                issues.push(ValidationIssue::new(
+                    &lt.file,
+                    format!("Invalid uncertainty [{}]", unc),
+                ));
+            }
+        }
+    }
+    for lt in unc_fdas {
+        let t = &lt.table;
+        match fdas
+            .iter()
+            .find(|fda| fda.machine == lt.machine && fda.applicator == lt.applicator)
+        {
+            None => issues.push(ValidationIssue::new(
+                &lt.file,
+                format!(
+                    "No field defining aperture table for the uncertainty table of machine [{}] \
+                    and applicator [{}]",
+                    lt.machine, lt.applicator
+                ),
+            )),
+            Some(fda) => {
+                if t.energies != fda.table.energies || t.ids != fda.table.ids {
+                    issues.push(ValidationIssue::new(
+                        &lt.file,
+                        format!(
+                            "Energies and FDAs differ from those in {}",
+                            fda.file.display()
+                        ),
+                    ));
+                }
+            }
+        }
+        for col in &t.table {
+            for unc in col.iter().filter(|v| is_invalid_factor(**v)) {
+                issues.push(ValidationIssue::new(
+                    &lt.file,
+                    format!("Invalid uncertainty [{}]", unc),
+                ));
+            }
+        }
+    }
+}
+
+// Check that the calibrations belong to a machine and energy of the output factor tables.
+fn check_calibrations(dirname: &str, ofs: &[LoadedTable<OFTable>]) -> Vec<ValidationIssue> {
+    let path_buf = Path::new(dirname).join(CALIBRATION_FILE_NAME);
+    if !path_buf.is_file() {
+        return vec![];
+    }
+    let table = match read_calibration_table(path_buf.clone()) {
+        Ok(table) => table,
+        Err(e) => return vec![ValidationIssue::new(&path_buf, e.to_string())],
+    };
+    let mut issues = vec![];
+    for c in &table.calibrations {
+        let machine_ofs: Vec<&LoadedTable<OFTable>> =
+            ofs.iter().filter(|of| of.machine == c.machine).collect();
+        if machine_ofs.is_empty() {
+            issues.push(ValidationIssue::new(
+                &path_buf,
+                format!(
+                    "No output factor table for the calibration of machine [{}] \
+                    effective from {}",
+                    c.machine, c.effective_date
+                ),
+            ));
+        } else if !machine_ofs
+            .iter()
+            .any(|of| of.table.get_energies().contains(&c.energy))
+        {
+            issues.push(ValidationIssue::new(
+                &path_buf,
+                format!(
+                    "Energy [{}] of the calibration of machine [{}] effective from {} \
+                    isn't in its output factor tables",
+                    c.energy, c.machine, c.effective_date
+                ),
+            ));
+        }
+    }
+    issues
+}
+
+// Check the tables next to the output factor and field defining aperture tables.
+fn validate_sidecars(
+    dirname: &str,
+    opt_format: Option<CsvFormat>,
+    ofs: &[LoadedTable<OFTable>],
+    fdas: &[LoadedTable<FdaTable>],
+) -> Result<Vec<ValidationIssue>, EmuError> {
+    let mut issues = vec![];
+    let pdds = read_tables(
+        list_files(dirname, Pdd::PREFIX)?,
+        |file| read_energy_table::<Pdd>(file, opt_format),
+        &mut issues,
+    );
+    check_energy_tables(&pdds, ofs, "PDD", &mut issues);
+    let obliquities = read_tables(
+        list_files(dirname, Obliquity::PREFIX)?,
+        |file| read_energy_table::<Obliquity>(file, opt_format),
+        &mut issues,
+    );
+    check_energy_tables(&obliquities, ofs, "obliquity", &mut issues);
+    let shields = read_tables(
+        list_files(dirname, Shield::PREFIX)?,
+        |file| read_energy_table::<Shield>(file, opt_format),
+        &mut issues,
+    );
+    check_energy_tables(&shields, ofs, "shield backscatter", &mut issues);
+    let unc_ofs = read_tables(
+        list_files(dirname, UNC_OF_PREFIX)?,
+        |file| read_of_table_with_format(file, opt_format),
+        &mut issues,
+    );
+    let unc_fdas = read_tables(
+        list_files(dirname, UNC_FDA_PREFIX)?,
+        |file| read_fda_table_with_format(file, opt_format),
+        &mut issues,
+    );
+    check_uncertainty_tables(&unc_ofs, &unc_fdas, ofs, fdas, &mut issues);
+    issues.extend(check_calibrations(dirname, ofs));
+    Ok(issues)
+}
+
+/// Check all the files with configuration data in a directory: the output factor and field
+/// defining aperture tables, the PDD, obliquity, shield and uncertainty tables next to them and
+/// the calibrations.
+/// Unlike load_data, every problem is reported instead of only the first one.
+pub fn validate_data(
+    dirname: &str,
+    reference: &ReferenceConditions,
+) -> Result<Vec<ValidationIssue>, EmuError> {
+    let (mut vof, mut vfda) = get_list_data_files(dirname)?;
+    let opt_format = load_csv_format(dirname)?;
+    vof.sort();
+    vfda.sort();
+    let mut issues = vec![];
+    if vof.is_empty() && vfda.is_empty() {
+        issues.push(ValidationIssue::new(
+            Path::new(dirname),
+            "No output factor or field defining aperture files found".to_owned(),
+        ));
+    }
+    let ofs = read_tables(
+        vof,
+        |file| read_of_table_with_format(file, opt_format),
+        &mut issues,
+    );
+    let fdas = read_tables(
+        vfda,
+        |file| read_fda_table_with_format(file, opt_format),
+        &mut issues,
+    );
+    issues.extend(validate_tables(&ofs, &fdas, reference));
+    issues.extend(validate_sidecars(dirname, opt_format, &ofs, &fdas)?);
+    if let Some(manifest) = load_manifest(dirname)? {
+        match verify_manifest(dirname, &manifest) {
+            Err(EmuError::ManifestMismatch(version, mismatches)) => {
+                let path_buf = Path::new(dirname).join(MANIFEST_FILE_NAME);
+                for mismatch in mismatches {
+                    issues.push(ValidationIssue::new(
+                        &path_buf,
+                        format!("{} since version [{}] was approved", mismatch, version),
+                    ));
+                }
+            }
+            res => res?,
+        }
+    }
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn of_table(ssds: &[f64], ofs: &[[f64; 2]]) -> OFTable {
+        let mut table = OFTable::new();
+        table.set_energies(vec![6.0, 12.0]);
+        table.set_zrefs(vec![1.36, 2.78]);
+        for (ssd, of) in ssds.iter().zip(ofs.iter()) {
+            table.add_output_factor_per_ssd(*ssd, of.to_vec()).unwrap();
+        }
+        table
+    }
+
+    fn fda_table(energies: Vec<f64>, fdas: &[(&str, usize, f64)]) -> FdaTable {
+        let mut table = FdaTable::new();
+        let n = energies.len();
+        table.set_energies(energies);
+        for (name, id, cf) in fdas {
+            table.add(name, *id, vec![*cf; n]).unwrap();
+        }
+        table
+    }
+
+    fn loaded<T>(file: &str, applicator: &str, table: T) -> LoadedTable<T> {
+        LoadedTable {
+            file: PathBuf::from(file),
+            machine: "Synergy2".to_owned(),
+            applicator: applicator.to_owned(),
+            table,
+        }
+    }
+
+    #[test]
+    fn validate_resources() {
+        let issues = validate_data("resources", &ReferenceConditions::default()).unwrap();
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+
+    #[test]
+    fn validate_issues() {
+        let ofs = vec![
+            loaded(
+                "of_10x10.csv",
+                "10x10",
+                of_table(
+                    &[95.0, 100.0, 100.0],
+                    &[[1.0, 0.99], [0.9, 0.9], [0.9, 0.9]],
+                ),
+            ),
+            loaded(
+                "of_6x6.csv",
+                "6x6",
+                of_table(
+                    &[95.0, 105.0, 100.0],
+                    &[[0.9, 0.9], [0.8, -0.8], [f64::NAN, 0.85]],
+                ),
+            ),
+            loaded("of_6x6_copy.csv", "6x6", of_table(&[95.0], &[[0.9, 0.9]])),
+            loaded("of_14x14.csv", "14x14", of_table(&[95.0], &[[0.9, 0.9]])),
+        ];
+        let fdas = vec![
+            loaded(
+                "fda_10x10.csv",
+                "10x10",
+                fda_table(vec![6.0, 12.0], &[("10x10", 1, 1.0), ("8x10", 1, 1.0)]),
+            ),
+            loaded(
+                "fda_6x6.csv",
+                "6x6",
+                fda_table(vec![6.0, 10.0], &[("6x6", 1, 1.0), ("4x6", 2, f64::NAN)]),
+            ),
+            loaded("fda_20x20.csv", "20x20", fda_table(vec![6.0, 12.0], &[])),
+        ];
+        let issues = validate_tables(&ofs, &fdas, &ReferenceConditions::default());
+        let messages: Vec<String> = issues.iter().map(|i| i.to_string()).collect();
+        let count = |file: &str, pattern: &str| {
+            messages
+                .iter()
+                .filter(|m| m.starts_with(file) && m.contains(pattern))
+                .count()
+        };
+        assert_eq!(count("of_6x6_copy.csv", "Duplicate output factor table"), 1);
+        assert_eq!(count("of_14x14.csv", "No field defining aperture table"), 1);
+        assert_eq!(count("fda_20x20.csv", "No output factor table"), 1);
+        // reported for the original and the duplicate output factor table
+        assert_eq!(count("fda_6x6.csv", "Energies [6.0, 10.0] differ"), 2);
+        assert_eq!(count("of_10x10.csv", "Duplicate SSD [100]"), 1);
+        assert_eq!(count("of_6x6.csv", "not sorted"), 1);
+        assert_eq!(count("of_6x6.csv", "Invalid output factor"), 2);
+        assert_eq!(count("of_10x10.csv", "instead of 1.0 for energy [12]"), 1);
+        assert_eq!(count("fda_10x10.csv", "Duplicate FDA id [1]"), 1);
+        assert_eq!(count("fda_6x6.csv", "Invalid correction factor"), 2);
+        assert_eq!(messages.len(), 13, "{:#?}", messages);
+    }
+
+    #[test]
+    fn validate_sidecars() {
+        let dir = std::env::temp_dir().join(format!("emu_check_validate_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in &["of_app_10x10.csv", "fda_app_10x10.csv", "obl_app_10x10.csv"] {
+            std::fs::copy(Path::new("resources").join(file), dir.join(file)).unwrap();
+        }
+        let write = |file: &str, content: &str| std::fs::write(dir.join(file), content).unwrap();
+        write(
+            "pdd_app_10x10.csv",
+            "Synergy2,,\nApplicator,10x10,\nDepth,6,12\n0,76,87\n1,95,NaN\n0.5,86,91\n",
+        );
+        write(
+            "shield_app_6x6.csv",
+            "Synergy2,,\nApplicator,6x6,\nDepth,6,12\n0.5,1.5,1.4\n1,1.6,1.5\n",
+        );
+        write(
+            "unc_of_app_10x10.csv",
+            "Synergy2,,,,,\nApplicator,10x10,,,,\nEnergy,4,6,8,10,12\n\
+            SSD/Zref,0.89,1.36,1.81,2.31,2.78\n100,0.01,0.01,0.01,0.01,0.01\n",
+        );
+        write(
+            CALIBRATION_FILE_NAME,
+            "machine,energy,cgy_per_mu,effective_date\n\
+            Synergy2,6,1.0,2020-01-01\nSynergy2,7,1.0,2020-01-01\nOther,6,1.0,2020-01-01\n",
+        );
+        let issues = validate_data(dir.to_str().unwrap(), &ReferenceConditions::default());
+        std::fs::remove_dir_all(&dir).unwrap();
+        let messages: Vec<String> = issues.unwrap().iter().map(|i| i.to_string()).collect();
+        let count = |file: &str, pattern: &str| {
+            messages
+                .iter()
+                .filter(|m| m.contains(file) && m.contains(pattern))
+                .count()
+        };
+        assert_eq!(count("pdd_app_10x10.csv", "Energies [4.0, 8.0, 10.0]"), 1);
+        assert_eq!(count("pdd_app_10x10.csv", "Depths are not sorted"), 1);
+        assert_eq!(count("pdd_app_10x10.csv", "Invalid PDD [%] [NaN]"), 1);
+        assert_eq!(count("shield_app_6x6.csv", "No output factor table"), 1);
+        assert_eq!(count("unc_of_app_10x10.csv", "SSDs differ"), 1);
+        assert_eq!(count(CALIBRATION_FILE_NAME, "Energy [7]"), 1);
+        assert_eq!(count(CALIBRATION_FILE_NAME, "machine [Other]"), 1);
+        assert_eq!(messages.len(), 7, "{:#?}", messages);
+    }
+}